@@ -0,0 +1,100 @@
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Instant;
+use socket2::{Socket, Domain, Type, Protocol};
+
+use crate::connection::client::receive_with_timeout;
+use crate::connection::protocol::{self, ServerMessage, ANNOUNCE_INTERVAL, ANNOUNCE_PORT, PROTOCOL_VERSION};
+
+//Una sessione che non si annuncia per 3 intervalli non viene più mostrata
+const ANNOUNCES_BEFORE_EXPIRY: u32 = 3;
+
+
+/// A session found on the LAN, `address` is where to send `Join`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredSession {
+    pub address: SocketAddr,
+    pub session_name: String,
+    pub presenter_name: String,
+    pub viewers: usize,
+    pub pin_required: bool,
+    last_seen: Instant,
+}
+
+
+/// Listens for the `Announce` broadcast by the servers and keeps the list of active sessions
+pub struct SessionBrowser {
+    sessions: Arc<Mutex<Vec<DiscoveredSession>>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl SessionBrowser {
+    pub fn start() -> Result<Self, io::Error> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        // Più client sulla stessa macchina devono poter ascoltare gli annunci
+        socket.set_reuse_address(true)?;
+        socket.bind(&SocketAddr::from(([0, 0, 0, 0], ANNOUNCE_PORT)).into())?;
+
+        let sessions = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let thread_sessions = Arc::clone(&sessions);
+        let thread_closed = Arc::clone(&closed);
+        thread::spawn(move || {
+            while !thread_closed.load(Ordering::Relaxed) {
+                let mut buf = [MaybeUninit::uninit(); 1024];
+                let received = receive_with_timeout(&socket, &mut buf);
+
+                let mut sessions = thread_sessions.lock().unwrap();
+                sessions.retain(|session: &DiscoveredSession| session.last_seen.elapsed() < ANNOUNCE_INTERVAL * ANNOUNCES_BEFORE_EXPIRY);
+
+                let Ok((amt, src)) = received else {
+                    continue;
+                };
+                let Some(address) = src.as_socket() else {
+                    continue;
+                };
+                let initialized_buf = unsafe {
+                    std::slice::from_raw_parts(buf.as_ptr() as *const u8, amt)
+                };
+
+                match protocol::decode::<ServerMessage>(initialized_buf) {
+                    // Le sessioni di un'altra versione non si potrebbero comunque aprire
+                    Ok(ServerMessage::Announce(announcement)) if announcement.version == PROTOCOL_VERSION => {
+                        let session = DiscoveredSession {
+                            address,
+                            session_name: announcement.session_name,
+                            presenter_name: announcement.presenter_name,
+                            viewers: announcement.viewers,
+                            pin_required: announcement.pin_required,
+                            last_seen: Instant::now(),
+                        };
+                        match sessions.iter_mut().find(|known| known.address == address) {
+                            Some(known) => *known = session,
+                            None => sessions.push(session),
+                        }
+                    },
+                    Ok(_) => {},
+                    Err(e) => println!("Invalid announcement from {}: {}", address, e),
+                }
+            }
+        });
+
+        Ok(Self { sessions, closed })
+    }
+
+    /// Sessions announced recently, in the order they were found
+    pub fn sessions(&self) -> Vec<DiscoveredSession> {
+        self.sessions.lock().unwrap().clone()
+    }
+}
+
+impl Drop for SessionBrowser {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
@@ -1,22 +1,77 @@
 use std::io::{self};
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 use socket2::{Socket, Domain, Type, Protocol, SockAddr};
-use std::net::{SocketAddr, Ipv4Addr, IpAddr};
+use std::net::{SocketAddr, IpAddr, UdpSocket};
 use std::mem::MaybeUninit;
+use crate::streamer::srtp::{KeyExchange, SrtpKey};
+use crate::streamer::streamer::RTCP_PORT_OFFSET;
+use crate::connection::protocol::{self, ChatMessage, ClientMessage, ErrorCode, InputEvent, ServerMessage, SessionInfo, StreamEvent, DEFAULT_DISCOVERY_PORT, HEARTBEAT_INTERVAL, MAX_CHAT_LEN, PROTOCOL_VERSION};
+
+/// What the client knows of the stream, from the events sent by the presenter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStatus {
+    pub paused: bool,
+    pub blanked: bool,
+    pub resolution: Option<(i32, i32)>, // solo dopo un cambio dell'area condivisa
+    pub ended: bool,
+    pub chat_enabled: bool,
+}
+
+impl StreamStatus {
+    fn apply(&mut self, event: StreamEvent) {
+        match event {
+            StreamEvent::Paused => self.paused = true,
+            StreamEvent::Resumed => self.paused = false,
+            StreamEvent::Blanked => self.blanked = true,
+            StreamEvent::Unblanked => self.blanked = false,
+            StreamEvent::ResolutionChanged { width, height } => self.resolution = Some((width, height)),
+            StreamEvent::SessionEnded => self.ended = true,
+            StreamEvent::ChatEnabled => self.chat_enabled = true,
+            StreamEvent::ChatDisabled => self.chat_enabled = false,
+        }
+    }
+}
 
 pub struct DiscoveryClient{
     local_port: u16,
     socket: Socket,
+    stream_status: Arc<Mutex<StreamStatus>>,
+    closed: Arc<AtomicBool>,
+    server: Option<(SocketAddr, u64)>, // server che ci ha accettato e token per uscire dalla sessione
+    nickname: Option<String>,
+    session: Option<SessionInfo>, // sessione in cui siamo entrati
+    srtp_key: Option<SrtpKey>, // chiave dello stream, già decifrata
+    removed: Arc<Mutex<Option<bool>>>, // Some(banned) se il presentatore ci ha tolto dalla sessione
+    chat: Arc<Mutex<Vec<ChatMessage>>>, // messaggi ricevuti da quando siamo entrati, più gli ultimi di prima
+    control_requested: Arc<AtomicBool>, // in attesa della risposta del presentatore
+    controlling: Arc<AtomicBool>, // il presentatore ci ha dato il controllo del suo desktop
 }
 
 impl DiscoveryClient {
-    pub fn new() -> Result<Self, io::Error> {
-        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    /// Opens the socket that receives the stream on `stream_port`, with 0 a free port is chosen
+    pub fn new(stream_port: u16) -> Result<Self, io::Error> {
+        // The server sends the video to our port, the audio to the next one and the RTCP of both to the two after, so all must be free
+        let mut attempts = if stream_port == 0 { 10 } else { 1 };
+        let socket = loop {
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+
+            socket.set_reuse_address(true)?;
 
-        socket.set_reuse_address(true)?;
+            // Bind the socket to an address
+            socket.bind(&SocketAddr::from(([0, 0, 0, 0], stream_port)).into())?;
 
-        // Bind the socket to an address
-        socket.bind(&SocketAddr::from(([0, 0, 0, 0], 0)).into())?;
+            let port = socket.local_addr()?.as_socket().map(|addr| addr.port()).unwrap_or(0);
+            let other_ports_free = (1..=1 + RTCP_PORT_OFFSET)
+                .all(|offset| port.checked_add(offset).is_some_and(|other| UdpSocket::bind(("0.0.0.0", other)).is_ok()));
+
+            attempts -= 1;
+            if other_ports_free || attempts == 0 {
+                break socket;
+            }
+        };
 
 
         //let local_addr = socket.local_addr()?;
@@ -34,18 +89,35 @@ impl DiscoveryClient {
     
 
 
-        Ok(DiscoveryClient { socket, local_port })
+        Ok(DiscoveryClient {
+            socket,
+            local_port,
+            stream_status: Arc::new(Mutex::new(StreamStatus::default())),
+            closed: Arc::new(AtomicBool::new(false)),
+            server: None,
+            nickname: None,
+            session: None,
+            srtp_key: None,
+            removed: Arc::new(Mutex::new(None)),
+            chat: Arc::new(Mutex::new(Vec::new())),
+            control_requested: Arc::new(AtomicBool::new(false)),
+            controlling: Arc::new(AtomicBool::new(false)),
+        })
     }
 
-    pub fn discover_server(&self,server_adress_ip:IpAddr) -> Result<(String,i32), io::Error> {
-
-
+    /// Name shown to the presenter in the viewer panel, sent with the next `discover_server`
+    pub fn set_nickname(&mut self, nickname: Option<String>) {
+        self.nickname = nickname;
+    }
 
-        let server_adress = SocketAddr::new(server_adress_ip, 9000);
+    /// Asks the server at `server_adress` to join its session and returns what is needed to receive the stream,
+    /// `pin` is checked by the server only if the session is protected.
+    /// If the session has a waiting room `waiting` is set while the presenter decides whether to admit us
+    pub fn discover_server(&mut self,server_adress:SocketAddr, pin: Option<&str>, waiting: &AtomicBool) -> Result<SessionInfo, io::Error> {
 
         let sock_addr = SockAddr::from(server_adress);
 
-        println!("Sending DISCOVERY message to {:?}", server_adress_ip);
+        println!("Sending Join message to {:?}", server_adress);
 
         let set_broadcast_result = self.socket.set_broadcast(true);
         match set_broadcast_result{
@@ -61,25 +133,23 @@ impl DiscoveryClient {
 
         let mut count = 13;
         let mut cond = true;
+        //la stessa chiave per tutti i Join, il Welcome può rispondere a uno qualsiasi
+        let key_exchange = KeyExchange::new();
 
     // Use a while loop to wait until we get a succesfull response or we exceed 26 seconds
      while cond {
-        let discovery_message = "DISCOVERY";
-        self.socket.send_to(discovery_message.as_bytes(), &sock_addr)?;
-        println!("Sent DISCOVERY message with local port: {}", self.local_port);
-
-
-        let mut buf = [MaybeUninit::uninit(); 1024];
-        let ris: Result<(String, i32), io::Error>=Result::Ok(("mdjisf".to_string(),4));
-
-        match ris {
-            Ok(_) => {
-                println!("Connection...");
-            },
-            Err(e) => {
-                println!("Error in connecting: {}", e);
-            },
-        }
+        let join_message = ClientMessage::Join {
+            version: PROTOCOL_VERSION,
+            stream_port: self.local_port,
+            pin: pin.map(|pin| pin.to_string()),
+            nickname: self.nickname.clone(),
+            key_share: Some(key_exchange.share()),
+        };
+        self.socket.send_to(&protocol::encode(&join_message), &sock_addr)?;
+        println!("Sent Join message with local port: {}", self.local_port);
+
+
+        let mut buf = [MaybeUninit::uninit(); 4096];
         let socket_response = receive_with_timeout(&self.socket, &mut buf);
   
         match socket_response {
@@ -88,17 +158,76 @@ impl DiscoveryClient {
                     std::slice::from_raw_parts(buf.as_ptr() as *const u8, amt)
                 };
 
-                let server_response = String::from_utf8_lossy(initialized_buf).to_string();
-
-                println!("Received response: {} from {:?}", server_response, src.as_socket_ipv4().unwrap().ip());
-
                 let ip_addr: IpAddr = src.as_socket().expect("no as socket works").ip();
 
                 println!("the Server IP is {}",ip_addr.to_string());
 
-                let client_ip = server_response.trim().to_string();
+                let session = match protocol::decode::<ServerMessage>(initialized_buf) {
+                    Ok(ServerMessage::Welcome(session)) => session,
+                    Ok(ServerMessage::Waiting) => {
+                        // si ripete il Join finché il presentatore non risponde, così il server sa che stiamo ancora aspettando
+                        if !waiting.swap(true, Ordering::Relaxed) {
+                            println!("Waiting for the presenter to admit us");
+                        }
+                        count = 13;
+                        thread::sleep(HEARTBEAT_INTERVAL);
+                        continue;
+                    },
+                    Ok(ServerMessage::Error { code: ErrorCode::Banned, .. }) => {
+                        return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                            "The presenter banned you from this session"));
+                    },
+                    Ok(ServerMessage::Error { code: ErrorCode::Denied, .. }) => {
+                        return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                            "The presenter did not admit you to the session"));
+                    },
+                    Ok(ServerMessage::Error { code: ErrorCode::WrongPin, .. }) => {
+                        return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                            "Wrong PIN: ask the presenter for the PIN of this session"));
+                    },
+                    Ok(ServerMessage::Error { code, message }) => {
+                        return Err(io::Error::new(io::ErrorKind::ConnectionRefused,
+                            format!("The server refused the connection ({}): {}", code, message)));
+                    },
+                    Ok(other) => {
+                        println!("Unexpected message while joining: {:?}", other);
+                        //conta come un tentativo, altrimenti chi manda altri messaggi ci terrebbe qui per sempre
+                        count -= 1;
+                        if count == 0 {
+                            break;
+                        }
+                        continue;
+                    },
+                    Err(e) => {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                            format!("Invalid reply from the server, it may use a different version: {}", e)));
+                    },
+                };
+
+                if session.version != PROTOCOL_VERSION {
+                    return Err(io::Error::new(io::ErrorKind::Unsupported,
+                        format!("The server uses protocol version {}, this client uses version {}", session.version, PROTOCOL_VERSION)));
+                }
 
-                return Ok((client_ip, self.local_port as i32));
+                let srtp_key = match session.srtp_key {
+                    Some(ref wrapped) => Some(key_exchange.decrypt(wrapped).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                        "The server sent a stream key this client cannot decrypt"))?),
+                    None => None,
+                };
+
+                println!("Joined session '{}' of {}", session.session_name, session.presenter_name);
+
+                //Connesso al server, il kernel consegna a questo socket i suoi messaggi
+                //e a udpsrc (stessa porta) i pacchetti RTP che arrivano da un'altra porta
+                if let Err(e) = self.socket.connect(&sock_addr) {
+                    println!("Failed to connect the discovery socket to the server: {}", e);
+                }
+                self.stream_status.lock().unwrap().chat_enabled = session.chat_enabled;
+                self.server = Some((server_adress, session.leave_token));
+                self.session = Some(session.clone());
+                self.srtp_key = srtp_key;
+
+                return Ok(session);
             },
             Err(e) => {
                 println!("Error in discovering the server: {}", e);
@@ -119,22 +248,189 @@ impl DiscoveryClient {
     ))
     }
 
-    pub fn notify_disconnection(&self) -> Result<(), io::Error> {
+    /// Starts the thread that keeps the session alive after `discover_server`: it sends the heartbeats
+    /// and listens for the events of the stream sent by the server, read with `stream_status`,
+    /// and for the chat messages, read with `chat`
+    pub fn start_session_thread(&self) -> Result<(), io::Error> {
+        let socket = self.socket.try_clone()?;
+        let stream_status = Arc::clone(&self.stream_status);
+        let closed = Arc::clone(&self.closed);
+        let removed = Arc::clone(&self.removed);
+        let chat = Arc::clone(&self.chat);
+        let control_requested = Arc::clone(&self.control_requested);
+        let controlling = Arc::clone(&self.controlling);
+
+        thread::spawn(move || {
+            let mut last_heartbeat: Option<Instant> = None;
+            while !closed.load(Ordering::Relaxed) {
+                if last_heartbeat.map_or(true, |sent| sent.elapsed() >= HEARTBEAT_INTERVAL) {
+                    //il socket è connesso al server dopo discover_server
+                    if let Err(e) = socket.send(&protocol::encode(&ClientMessage::Heartbeat)) {
+                        println!("Failed to send heartbeat: {}", e);
+                    }
+                    last_heartbeat = Some(Instant::now());
+                }
+
+                let mut buf = [MaybeUninit::uninit(); 4096];
+                let Ok((amt, _)) = receive_with_timeout(&socket, &mut buf) else {
+                    continue;
+                };
+                let initialized_buf = unsafe {
+                    std::slice::from_raw_parts(buf.as_ptr() as *const u8, amt)
+                };
+                match protocol::decode::<ServerMessage>(initialized_buf) {
+                    Ok(ServerMessage::Event(event)) => {
+                        println!("Stream event from the server: {:?}", event);
+                        stream_status.lock().unwrap().apply(event);
+                        if event == StreamEvent::SessionEnded {
+                            //la sessione non esiste più, niente heartbeat
+                            closed.store(true, Ordering::Relaxed);
+                        }
+                    },
+                    Ok(ServerMessage::Removed { banned }) => {
+                        println!("The presenter removed us from the session");
+                        *removed.lock().unwrap() = Some(banned);
+                        // il server non ci conosce più, inutile mandare altri heartbeat
+                        closed.store(true, Ordering::Relaxed);
+                    },
+                    Ok(ServerMessage::Chat(message)) => chat.lock().unwrap().push(message),
+                    Ok(ServerMessage::Error { code: ErrorCode::ChatDisabled, .. }) => {
+                        // il messaggio è arrivato dopo che il presentatore ha chiuso la chat
+                        stream_status.lock().unwrap().chat_enabled = false;
+                    },
+                    Ok(ServerMessage::Control { granted }) => {
+                        println!("Remote control {}", if granted { "granted" } else { "revoked" });
+                        controlling.store(granted, Ordering::Relaxed);
+                        control_requested.store(false, Ordering::Relaxed);
+                    },
+                    Ok(ServerMessage::Error { code: ErrorCode::ControlUnavailable, message }) => {
+                        println!("{}", message);
+                        control_requested.store(false, Ordering::Relaxed);
+                    },
+                    Ok(other) => println!("Unexpected message from the server: {:?}", other),
+                    Err(e) => println!("Invalid message from the server: {}", e),
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Session joined with `discover_server`
+    pub fn session(&self) -> Option<&SessionInfo> {
+        self.session.as_ref()
+    }
+
+    /// Key to decrypt the stream, None if the presenter sends it unencrypted
+    pub fn srtp_key(&self) -> Option<&SrtpKey> {
+        self.srtp_key.as_ref()
+    }
+
+    pub fn stream_status(&self) -> StreamStatus {
+        *self.stream_status.lock().unwrap()
+    }
+
+    /// `Some(banned)` once the presenter removed us from the session
+    pub fn removed(&self) -> Option<bool> {
+        *self.removed.lock().unwrap()
+    }
 
-        let broadcast_addr = SocketAddr::new(Ipv4Addr::BROADCAST.into(), 9000);
-        let server_addr = SockAddr::from(broadcast_addr);
-        self.socket.set_broadcast(true)?;
-        println!("Sending DISCONNECT message to {:?}", server_addr.as_socket_ipv4().unwrap().ip());
+    /// Chat messages of the session, oldest first
+    pub fn chat(&self) -> Vec<ChatMessage> {
+        self.chat.lock().unwrap().clone()
+    }
+
+    /// Sends a chat message to the server, which relays it to everyone (us included)
+    pub fn send_chat(&self, text: &str) -> Result<(), io::Error> {
+        let text: String = text.trim().chars().take(MAX_CHAT_LEN).collect();
+        if text.is_empty() {
+            return Ok(());
+        }
+        //il socket è connesso al server dopo discover_server
+        self.socket.send(&protocol::encode(&ClientMessage::Chat { text }))?;
+        Ok(())
+    }
 
-        let disconnect_message = "DISCONNECT";
-        self.socket.send_to(disconnect_message.as_bytes(), &server_addr)?;
-        println!("Sent DISCONNECT message with local port: {}", self.local_port);
+    /// Tells the presenter where we clicked on the video, `x` and `y` from 0 to 1 across the frame
+    pub fn send_point(&self, x: f64, y: f64) -> Result<(), io::Error> {
+        self.socket.send(&protocol::encode(&ClientMessage::Point { x, y }))?;
+        Ok(())
+    }
+
+    /// Asks the presenter to control the desktop, the answer is read with `in_control`
+    pub fn request_control(&self) -> Result<(), io::Error> {
+        self.socket.send(&protocol::encode(&ClientMessage::RequestControl))?;
+        self.control_requested.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Gives the control back, or withdraws the request
+    pub fn release_control(&self) -> Result<(), io::Error> {
+        self.socket.send(&protocol::encode(&ClientMessage::ReleaseControl))?;
+        self.control_requested.store(false, Ordering::Relaxed);
+        self.controlling.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn control_requested(&self) -> bool {
+        self.control_requested.load(Ordering::Relaxed)
+    }
+
+    pub fn in_control(&self) -> bool {
+        self.controlling.load(Ordering::Relaxed)
+    }
+
+    /// Flag set while we control the desktop, for the window of `StreamerClient`
+    pub fn control_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.controlling)
+    }
+
+    /// Sends the input of the video window on its own copy of the socket,
+    /// so the GStreamer threads don't need the lock of the `DiscoveryClient`
+    pub fn input_sender(&self) -> Result<InputSender, io::Error> {
+        Ok(InputSender {
+            socket: self.socket.try_clone()?,
+        })
+    }
+
+    /// Tells the server we joined that we are leaving, only that server receives the message
+    pub fn notify_disconnection(&self) -> Result<(), io::Error> {
+        let Some((server_addr, token)) = self.server else {
+            //mai entrati in una sessione, non c'è nessuno da avvisare
+            return Ok(());
+        };
+        println!("Sending Leave message to {}", server_addr);
+
+        self.socket.send_to(&protocol::encode(&ClientMessage::Leave { token }), &SockAddr::from(server_addr))?;
+        println!("Sent Leave message with local port: {}", self.local_port);
 
         Ok(())
     }
     
 }
 
+/// Mouse and keyboard of the viewer for the presenter's desktop, see `DiscoveryClient::input_sender`
+pub struct InputSender {
+    socket: Socket,
+}
+
+impl InputSender {
+    pub fn send(&self, event: InputEvent) -> Result<(), io::Error> {
+        //il socket è connesso al server dopo discover_server
+        self.socket.send(&protocol::encode(&ClientMessage::Input(event)))?;
+        Ok(())
+    }
+}
+
+/// Reads the address typed by the user: `ip` uses the default discovery port, `ip:port` another one
+pub fn parse_server_address(input: &str) -> Option<SocketAddr> {
+    let input = input.trim();
+    match input.parse::<IpAddr>() {
+        Ok(ip) => Some(SocketAddr::new(ip, DEFAULT_DISCOVERY_PORT)),
+        Err(_) => input.parse::<SocketAddr>().ok(),
+    }
+}
+
 pub fn receive_with_timeout(socket: &Socket, buf: &mut [MaybeUninit<u8>]) -> Result<(usize, SockAddr), String> {
     //Set the read timeout
     socket
@@ -155,7 +451,10 @@ impl Drop for DiscoveryClient {
         fn drop(&mut self) {
             // Perform cleanup actions when the DiscoveryClient is dropped
             println!("Dropping DiscoveryClient and closing socket bound to port {}", self.local_port);
-            self.notify_disconnection().unwrap();
+            self.closed.store(true, Ordering::Relaxed);
+            if let Err(e) = self.notify_disconnection() {
+                println!("Failed to notify the disconnection: {}", e);
+            }
     
             // Explicitly set the socket to None to close it
             // This is not strictly necessary because Rust automatically drops the socket
@@ -165,3 +464,26 @@ impl Drop for DiscoveryClient {
     }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_without_port_uses_the_default_one() {
+        assert_eq!(parse_server_address("192.168.1.10"), Some(SocketAddr::from(([192, 168, 1, 10], DEFAULT_DISCOVERY_PORT))));
+        assert_eq!(parse_server_address("  10.0.0.1 "), Some(SocketAddr::from(([10, 0, 0, 1], DEFAULT_DISCOVERY_PORT))));
+    }
+
+    #[test]
+    fn address_with_port() {
+        assert_eq!(parse_server_address("192.168.1.10:9002"), Some(SocketAddr::from(([192, 168, 1, 10], 9002))));
+        assert_eq!(parse_server_address("[::1]:9000"), "[::1]:9000".parse().ok());
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        for input in ["", "localhost", "192.168.1", "192.168.1.10:", "192.168.1.10:70000", "192.168.1.10:port"] {
+            assert_eq!(parse_server_address(input), None, "{}", input);
+        }
+    }
+}
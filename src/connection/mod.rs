@@ -1,2 +1,5 @@
 pub mod server;
-pub mod client;
\ No newline at end of file
+pub mod client;
+pub mod registry;
+pub mod protocol;
+pub mod browser;
\ No newline at end of file
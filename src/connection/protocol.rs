@@ -0,0 +1,295 @@
+use std::fmt;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+
+/// Increased every time the messages change in a way older peers can't understand
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Port where `DiscoveryServer` listens when the presenter doesn't choose another one
+pub const DEFAULT_DISCOVERY_PORT: u16 = 9000;
+/// Port where every `DiscoveryServer` broadcasts its `Announce` and the clients look for sessions
+pub const ANNOUNCE_PORT: u16 = 8999;
+
+/// How often a client tells the server it is still watching
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+/// A client that sends nothing for this long is considered gone and stops receiving the stream
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often a server announces its session on the LAN
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+/// Longest chat message in characters, so that every message fits in one datagram
+pub const MAX_CHAT_LEN: usize = 280;
+
+
+/// Messages sent by `DiscoveryClient` to the discovery port of the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Asks to receive the stream on `stream_port` (audio on `stream_port + 1`, the RTCP of both on the two after),
+    /// `pin` is needed only if the presenter protected the session
+    Join {
+        version: u32,
+        stream_port: u16,
+        #[serde(default)]
+        pin: Option<String>,
+        #[serde(default)]
+        nickname: Option<String>, // shown to the presenter in the viewer panel
+        /// Hex X25519 public key of the client, the server uses it to encrypt the SRTP key in `Welcome`
+        #[serde(default)]
+        key_share: Option<String>,
+    },
+    Heartbeat,
+    /// `token` is the `leave_token` received in `Welcome`, so nobody else can disconnect this client
+    Leave { token: u64 },
+    /// Chat message for the presenter and the other viewers, ignored if the client did not join
+    Chat { text: String },
+    /// The viewer clicked on the video: `x` and `y` go from 0 to 1 across the received frame
+    Point { x: f64, y: f64 },
+    /// Asks the presenter to drive its mouse and keyboard, answered with `Control`
+    RequestControl,
+    /// The viewer gives the control back, or withdraws its request
+    ReleaseControl,
+    /// Sent only while the presenter granted the control, ignored otherwise
+    Input(InputEvent),
+}
+
+
+/// Mouse and keyboard of the viewer on the received video, coordinates from 0 to 1 across the frame
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "input", rename_all = "snake_case")]
+pub enum InputEvent {
+    MouseMove { x: f64, y: f64 },
+    /// `button` as in X11: 1 left, 2 middle, 3 right
+    MouseButton { x: f64, y: f64, button: u8, pressed: bool },
+    /// Wheel steps, positive `dy` scrolls down and positive `dx` right
+    Scroll { x: f64, y: f64, dx: f64, dy: f64 },
+    /// `key` is the X keysym name, e.g. "a", "Return", "Shift_L", as GStreamer sends it
+    Key { key: String, pressed: bool },
+}
+
+
+/// Messages sent by `DiscoveryServer` to the clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Welcome(SessionInfo),
+    Error { code: ErrorCode, message: String },
+    /// The session has a waiting room: the presenter still has to admit the client, which keeps sending `Join`
+    Waiting,
+    /// The presenter removed the client from the session, with `banned` it can't join again
+    Removed { banned: bool },
+    Event(StreamEvent),
+    /// Broadcast to the LAN from the discovery socket, so its source address is where to send `Join`
+    Announce(SessionAnnouncement),
+    /// Chat message of the presenter or of a viewer, relayed to everyone in the session
+    Chat(ChatMessage),
+    /// The presenter granted or revoked the control of its desktop
+    Control { granted: bool },
+}
+
+
+/// Changes of the stream made by the presenter, sent to every connected client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StreamEvent {
+    Paused,
+    Resumed,
+    Blanked,
+    Unblanked,
+    /// The presenter shares a different area, `width` x `height` before scaling
+    ResolutionChanged { width: i32, height: i32 },
+    /// The presenter stopped the stream, the clients can close everything
+    SessionEnded,
+    /// The presenter turned the chat on or off for the rest of the session
+    ChatEnabled,
+    ChatDisabled,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub author: String,       // nickname of the viewer or name of the presenter
+    pub from_presenter: bool,
+    pub text: String,
+}
+
+
+/// What the session browser of the clients shows for each session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionAnnouncement {
+    pub version: u32,
+    pub session_name: String,
+    pub presenter_name: String,
+    pub viewers: usize,
+    pub pin_required: bool,
+}
+
+
+/// Everything a client needs to receive the stream, sent in reply to `Join`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub version: u32,
+    pub session_name: String,
+    pub presenter_name: String,
+    pub codec: String,
+    pub video_caps: String,           // caps of the RTP stream, for udpsrc
+    pub audio_caps: Option<String>,   // None if the presenter doesn't send audio
+    pub client_ip: String,            // address of the client as seen by the server
+    pub stream_port: u16,
+    pub leave_token: u64,
+    /// Master key of the SRTP streams, sent only to the clients that passed the PIN and the waiting room
+    /// and encrypted for each of them. None if the presenter sends the stream unencrypted
+    pub srtp_key: Option<WrappedKey>,
+    pub chat_enabled: bool,
+    pub remote_control: bool, // the presenter accepts requests to control its desktop
+}
+
+
+/// SRTP master key encrypted for one client, see `SrtpKey::wrap`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WrappedKey {
+    pub server_share: String, // hex X25519 public key of the server, new for every Welcome
+    pub key: String,          // hex, ChaCha20-Poly1305
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    IncompatibleVersion,
+    MalformedMessage,
+    WrongPin,
+    Denied,
+    Banned,
+    ChatDisabled,
+    ControlUnavailable,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCode::IncompatibleVersion => write!(f, "incompatible version"),
+            ErrorCode::MalformedMessage => write!(f, "malformed message"),
+            ErrorCode::WrongPin => write!(f, "wrong PIN"),
+            ErrorCode::Denied => write!(f, "denied by the presenter"),
+            ErrorCode::Banned => write!(f, "banned by the presenter"),
+            ErrorCode::ChatDisabled => write!(f, "chat disabled by the presenter"),
+            ErrorCode::ControlUnavailable => write!(f, "remote control not available"),
+        }
+    }
+}
+
+
+pub fn encode<T: Serialize>(message: &T) -> Vec<u8> {
+    //i messaggi sono solo stringhe e numeri, la serializzazione non può fallire
+    serde_json::to_vec(message).expect("Failed to serialize message")
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, serde_json::Error> {
+    serde_json::from_slice(bytes)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Serialize + DeserializeOwned>(message: &T) -> T {
+        decode(&encode(message)).unwrap()
+    }
+
+    fn session() -> SessionInfo {
+        SessionInfo {
+            version: PROTOCOL_VERSION,
+            session_name: "Demo".to_string(),
+            presenter_name: "Presenter".to_string(),
+            codec: "H264".to_string(),
+            video_caps: "application/x-rtp, media=(string)video".to_string(),
+            audio_caps: None,
+            client_ip: "192.168.1.10".to_string(),
+            stream_port: 5000,
+            leave_token: 42,
+            srtp_key: Some(WrappedKey { server_share: "ab".repeat(32), key: "cd".repeat(46) }),
+            chat_enabled: true,
+            remote_control: false,
+        }
+    }
+
+    #[test]
+    fn client_messages_round_trip() {
+        let messages = [
+            ClientMessage::Join {
+                version: PROTOCOL_VERSION,
+                stream_port: 5000,
+                pin: Some("1234".to_string()),
+                nickname: Some("Viewer".to_string()),
+                key_share: Some("ab".repeat(32)),
+            },
+            ClientMessage::Heartbeat,
+            ClientMessage::Leave { token: u64::MAX },
+            ClientMessage::Chat { text: "ciao".to_string() },
+            ClientMessage::Point { x: 0.25, y: 0.75 },
+            ClientMessage::RequestControl,
+            ClientMessage::ReleaseControl,
+            ClientMessage::Input(InputEvent::MouseButton { x: 0.5, y: 0.5, button: 1, pressed: true }),
+            ClientMessage::Input(InputEvent::Key { key: "Return".to_string(), pressed: false }),
+        ];
+        for message in messages {
+            assert_eq!(round_trip(&message), message);
+        }
+    }
+
+    #[test]
+    fn server_messages_round_trip() {
+        let messages = [
+            ServerMessage::Welcome(session()),
+            ServerMessage::Error { code: ErrorCode::WrongPin, message: "wrong".to_string() },
+            ServerMessage::Waiting,
+            ServerMessage::Removed { banned: true },
+            ServerMessage::Event(StreamEvent::ResolutionChanged { width: 1280, height: 720 }),
+            ServerMessage::Announce(SessionAnnouncement {
+                version: PROTOCOL_VERSION,
+                session_name: "Demo".to_string(),
+                presenter_name: "Presenter".to_string(),
+                viewers: 3,
+                pin_required: true,
+            }),
+            ServerMessage::Chat(ChatMessage { author: "Viewer".to_string(), from_presenter: false, text: "ciao".to_string() }),
+            ServerMessage::Control { granted: true },
+        ];
+        for message in messages {
+            assert_eq!(round_trip(&message), message);
+        }
+    }
+
+    #[test]
+    fn join_of_another_version_is_still_read() {
+        // il server deve poter leggere la versione per rispondere IncompatibleVersion, non MalformedMessage
+        let join = format!(r#"{{"type":"join","version":{},"stream_port":5000}}"#, PROTOCOL_VERSION + 1);
+        let message: ClientMessage = decode(join.as_bytes()).unwrap();
+        assert_eq!(message, ClientMessage::Join {
+            version: PROTOCOL_VERSION + 1,
+            stream_port: 5000,
+            pin: None,
+            nickname: None,
+            key_share: None,
+        });
+    }
+
+    #[test]
+    fn welcome_keeps_the_version_of_the_server() {
+        let welcome = ServerMessage::Welcome(SessionInfo { version: PROTOCOL_VERSION + 1, ..session() });
+        match round_trip(&welcome) {
+            ServerMessage::Welcome(session) => assert_ne!(session.version, PROTOCOL_VERSION),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_messages_are_rejected() {
+        assert!(decode::<ClientMessage>(br#"{"type":"teleport"}"#).is_err());
+        assert!(decode::<ServerMessage>(br#"{"type":"welcome"}"#).is_err());
+        assert!(decode::<ClientMessage>(b"not json").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use crate::streamer::pointer::PointerMark;
+use crate::connection::protocol::InputEvent;
+
+
+/// Change of the viewers, sent by `DiscoveryServer` to the thread that updates `ScreenStreamer`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientEvent {
+    Joined(SocketAddr),
+    Left(SocketAddr),
+    Pointed(PointerMark),
+    /// The viewer that now controls the desktop, `None` when nobody does
+    ControlChanged(Option<SocketAddr>),
+    /// Input of the viewer in control, to inject on the desktop of the presenter
+    Input(InputEvent),
+}
+
+
+/// Join requests waiting in the waiting room, sent by `DiscoveryServer` to the GUI of the presenter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdmissionEvent {
+    Requested(SocketAddr, Option<String>), // client e nickname
+    /// The client stopped asking before the presenter answered
+    Withdrawn(SocketAddr),
+}
+
+
+/// Where a viewer is with the remote control of the desktop
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ControlStatus {
+    #[default]
+    None,
+    Requested,
+    Granted,
+}
+
+
+#[derive(Debug, Clone)]
+struct ClientEntry {
+    addr: SocketAddr,
+    nickname: Option<String>,
+    joined: Instant,
+    last_seen: Instant,
+    token: u64, // given to the client when it joins, needed to leave
+    control: ControlStatus,
+}
+
+
+/// What the presenter sees of a viewer in the viewer panel
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewerInfo {
+    pub addr: SocketAddr,
+    pub nickname: Option<String>,
+    pub joined: Instant,
+    pub last_seen: Instant,
+    pub control: ControlStatus,
+}
+
+
+/// Viewers receiving the stream, at most one entry for each ip+port,
+/// with the last time each of them was heard
+#[derive(Debug, Clone, Default)]
+pub struct ClientRegistry {
+    clients: Vec<ClientEntry>,
+}
+
+impl ClientRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns false if the client was already registered (e.g. a repeated Join)
+    pub fn add(&mut self, addr: SocketAddr) -> bool {
+        if self.touch(&addr) {
+            return false;
+        }
+        self.clients.push(ClientEntry {
+            addr,
+            nickname: None,
+            joined: Instant::now(),
+            last_seen: Instant::now(),
+            token: rand::random(),
+            control: ControlStatus::None,
+        });
+        true
+    }
+
+    /// Records that the client is still alive, returns false if it is not registered
+    pub fn touch(&mut self, addr: &SocketAddr) -> bool {
+        match self.clients.iter_mut().find(|entry| entry.addr == *addr) {
+            Some(entry) => {
+                entry.last_seen = Instant::now();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Like `touch`, without updating the last time the client was heard
+    pub fn contains(&self, addr: &SocketAddr) -> bool {
+        self.clients.iter().any(|entry| entry.addr == *addr)
+    }
+
+    pub fn set_nickname(&mut self, addr: &SocketAddr, nickname: Option<String>) {
+        if let Some(entry) = self.clients.iter_mut().find(|entry| entry.addr == *addr) {
+            entry.nickname = nickname;
+        }
+    }
+
+    pub fn nickname(&self, addr: &SocketAddr) -> Option<String> {
+        self.clients.iter().find(|entry| entry.addr == *addr).and_then(|entry| entry.nickname.clone())
+    }
+
+    /// Returns false if the client is not registered
+    pub fn set_control(&mut self, addr: &SocketAddr, control: ControlStatus) -> bool {
+        match self.clients.iter_mut().find(|entry| entry.addr == *addr) {
+            Some(entry) => {
+                entry.control = control;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// The viewer that controls the desktop, there is at most one
+    pub fn controller(&self) -> Option<SocketAddr> {
+        self.clients.iter().find(|entry| entry.control == ControlStatus::Granted).map(|entry| entry.addr)
+    }
+
+    /// Token the client must send with its Leave message
+    pub fn token(&self, addr: &SocketAddr) -> Option<u64> {
+        self.clients.iter().find(|entry| entry.addr == *addr).map(|entry| entry.token)
+    }
+
+    /// Removes the clients not heard for more than `timeout` and returns them
+    pub fn remove_expired(&mut self, timeout: Duration) -> Vec<SocketAddr> {
+        let expired: Vec<SocketAddr> = self.clients.iter()
+            .filter(|entry| entry.last_seen.elapsed() > timeout)
+            .map(|entry| entry.addr)
+            .collect();
+        self.clients.retain(|entry| entry.last_seen.elapsed() <= timeout);
+        expired
+    }
+
+    /// Returns false if the client was not registered
+    pub fn remove(&mut self, addr: &SocketAddr) -> bool {
+        let len = self.clients.len();
+        self.clients.retain(|entry| entry.addr != *addr);
+        self.clients.len() != len
+    }
+
+    /// Like `remove`, but only if `token` is the one given to that client
+    pub fn remove_with_token(&mut self, addr: &SocketAddr, token: u64) -> bool {
+        if self.token(addr) != Some(token) {
+            return false;
+        }
+        self.remove(addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.clients.iter().map(|entry| &entry.addr)
+    }
+
+    pub fn viewers(&self) -> Vec<ViewerInfo> {
+        self.clients.iter()
+            .map(|entry| ViewerInfo {
+                addr: entry.addr,
+                nickname: entry.nickname.clone(),
+                joined: entry.joined,
+                last_seen: entry.last_seen,
+                control: entry.control,
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::protocol::CLIENT_TIMEOUT;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([192, 168, 1, 10], port))
+    }
+
+    // come se il client non si facesse sentire da `age`
+    fn make_silent(registry: &mut ClientRegistry, client: SocketAddr, age: Duration) {
+        let entry = registry.clients.iter_mut().find(|entry| entry.addr == client).unwrap();
+        entry.last_seen = Instant::now().checked_sub(age).unwrap();
+    }
+
+    #[test]
+    fn repeated_join_is_added_once() {
+        let mut registry = ClientRegistry::new();
+        assert!(registry.add(addr(5000)));
+        let token = registry.token(&addr(5000));
+        assert!(!registry.add(addr(5000)));
+        assert_eq!(registry.len(), 1);
+        assert_eq!(registry.token(&addr(5000)), token);
+
+        // stesso ip ma un'altra porta: è un altro client
+        assert!(registry.add(addr(5002)));
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn only_known_clients_are_removed() {
+        let mut registry = ClientRegistry::new();
+        registry.add(addr(5000));
+        assert!(!registry.remove(&addr(5002)));
+        assert!(registry.remove(&addr(5000)));
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn silent_clients_expire() {
+        let mut registry = ClientRegistry::new();
+        registry.add(addr(5000));
+        registry.add(addr(5002));
+        make_silent(&mut registry, addr(5000), CLIENT_TIMEOUT + Duration::from_secs(1));
+
+        assert_eq!(registry.remove_expired(CLIENT_TIMEOUT), vec![addr(5000)]);
+        assert_eq!(registry.iter().collect::<Vec<_>>(), vec![&addr(5002)]);
+        assert!(registry.remove_expired(CLIENT_TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn heartbeat_keeps_a_client() {
+        let mut registry = ClientRegistry::new();
+        registry.add(addr(5000));
+        make_silent(&mut registry, addr(5000), CLIENT_TIMEOUT + Duration::from_secs(1));
+        assert!(registry.touch(&addr(5000)));
+        assert!(registry.remove_expired(CLIENT_TIMEOUT).is_empty());
+        assert!(!registry.touch(&addr(6000)));
+    }
+
+    #[test]
+    fn leave_needs_the_right_token() {
+        let mut registry = ClientRegistry::new();
+        registry.add(addr(5000));
+        let token = registry.token(&addr(5000)).unwrap();
+
+        assert!(!registry.remove_with_token(&addr(5000), token.wrapping_add(1)));
+        assert!(!registry.remove_with_token(&addr(5002), token));
+        assert_eq!(registry.len(), 1);
+        assert!(registry.remove_with_token(&addr(5000), token));
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn only_the_granted_viewer_controls() {
+        let mut registry = ClientRegistry::new();
+        registry.add(addr(5000));
+        registry.add(addr(5002));
+        registry.set_control(&addr(5000), ControlStatus::Requested);
+        assert_eq!(registry.controller(), None);
+        registry.set_control(&addr(5002), ControlStatus::Granted);
+        assert_eq!(registry.controller(), Some(addr(5002)));
+        assert!(!registry.set_control(&addr(6000), ControlStatus::Granted));
+    }
+}
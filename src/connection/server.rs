@@ -1,38 +1,534 @@
-use std::net::UdpSocket;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{self, Receiver,TryRecvError};
 use std::error::Error;
 use std::io::{self,ErrorKind};
+use std::time::{Duration, Instant};
+use crate::streamer::pointer::PointerMark;
+use crate::streamer::srtp::{self, SrtpKey};
 
 use crate::ControlMessage;
+use crate::connection::registry::{AdmissionEvent, ClientEvent, ClientRegistry, ControlStatus, ViewerInfo};
+use crate::connection::protocol::{self, ChatMessage, ClientMessage, ErrorCode, InputEvent, ServerMessage, SessionAnnouncement, SessionInfo, StreamEvent, ANNOUNCE_INTERVAL, ANNOUNCE_PORT, CLIENT_TIMEOUT, MAX_CHAT_LEN, PROTOCOL_VERSION};
+
+//Ogni quanto si controlla se qualche client ha smesso di mandare heartbeat
+const EVICTION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+//Quanto si aspetta un datagram prima di tornare a guardare i messaggi di controllo e i timer
+const LISTENER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_NICKNAME_LEN: usize = 32;
+//La cronologia della chat resta in memoria per tutta la sessione, ma chi entra riceve solo gli ultimi messaggi
+const MAX_CHAT_HISTORY: usize = 500;
+const CHAT_HISTORY_ON_JOIN: usize = 50;
+
+// I campi di ClientMessage::Join
+struct JoinRequest {
+    version: u32,
+    stream_port: u16,
+    pin: Option<String>,
+    nickname: Option<String>,
+    key_share: Option<String>,
+}
+
+// Un client nella sala d'attesa, le risposte vanno a `src` e lo stream a `client`
+struct PendingJoin {
+    src: SocketAddr,
+    client: SocketAddr,
+    nickname: Option<String>,
+    key_share: Option<String>,
+    last_seen: Instant,
+}
+
 pub struct DiscoveryServer {
-    sender: mpsc::Sender<String>,
-    clients: String,
+    sender: mpsc::Sender<ClientEvent>,
+    clients: ClientRegistry,
+    paused: bool,
+    blanked: bool,
+    session: SessionInfo, // client_ip e stream_port vengono riempiti per ogni client
+    socket: UdpSocket,
+    pin: Option<String>,
+    srtp_key: Option<SrtpKey>, // None = stream in chiaro, mai inviata così com'è
+    admission: Option<async_std::channel::Sender<AdmissionEvent>>, // None = nessuna sala d'attesa
+    pending: Vec<PendingJoin>,
+    banned: Vec<IpAddr>, // per tutta la durata della sessione
+    viewers: Arc<Mutex<Vec<ViewerInfo>>>, // copia dei client letta dalla GUI
+    chat: Arc<Mutex<Vec<ChatMessage>>>,   // cronologia della chat, letta dalla GUI del presentatore
 }
 
-/* enum ControlMessage {
-    Pause,
-    Resume,
-    Stop,
-} */
+// Un Join che non si riesce a decodificare, ad esempio di un'altra versione del protocollo
+fn looks_like_join(bytes: &[u8]) -> bool {
+    protocol::decode::<serde_json::Value>(bytes)
+        .is_ok_and(|value| value.get("type").and_then(|kind| kind.as_str()) == Some("join"))
+}
 
 impl DiscoveryServer {
-    pub fn new(sender: mpsc::Sender<String>) -> Self {
-        Self {
+    /// Binds the discovery socket on `port`, so a port already used by another session is reported here.
+    /// With a `pin` only the clients that send it can join, with `admission` every new client waits
+    /// until the presenter answers with `ControlMessage::Admit` or `ControlMessage::Deny`.
+    /// `srtp_key` is encrypted for every admitted client with the key it sent in `Join`
+    pub fn new(sender: mpsc::Sender<ClientEvent>, session: SessionInfo, port: u16, pin: Option<String>, srtp_key: Option<SrtpKey>,
+        admission: Option<async_std::channel::Sender<AdmissionEvent>>) -> Result<Self, io::Error> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_read_timeout(Some(LISTENER_POLL_INTERVAL))?;
+        socket.set_broadcast(true)?;
+        println!("Discovery server listening on port {}", port);
+
+        Ok(Self {
             sender,
-            clients: String::new(),
+            clients: ClientRegistry::new(),
+            paused: false,
+            blanked: false,
+            session,
+            socket,
+            pin,
+            srtp_key,
+            admission,
+            pending: Vec::new(),
+            banned: Vec::new(),
+            viewers: Arc::new(Mutex::new(Vec::new())),
+            chat: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /// Viewers of the session, updated by the discovery thread every time they change
+    pub fn viewers(&self) -> Arc<Mutex<Vec<ViewerInfo>>> {
+        Arc::clone(&self.viewers)
+    }
+
+    /// Chat messages of the session, oldest first, including the ones of the presenter
+    pub fn chat(&self) -> Arc<Mutex<Vec<ChatMessage>>> {
+        Arc::clone(&self.chat)
+    }
+
+    fn publish_viewers(&self) {
+        *self.viewers.lock().unwrap() = self.clients.viewers();
+    }
+
+    fn send(&self, socket: &UdpSocket, message: &ServerMessage, addr: &SocketAddr) {
+        if let Err(e) = socket.send_to(&protocol::encode(message), addr) {
+            println!("Failed to send {:?} to {}: {}", message, addr, e);
+        }
+    }
+
+    /// A registered client or one waiting to be admitted
+    fn is_known_source(&self, src: &SocketAddr) -> bool {
+        self.clients.contains(src) || self.pending.iter().any(|pending| pending.src == *src)
+    }
+
+    /// Sends `message` to every connected client, on the same port it used to join
+    fn notify_clients(&self, socket: &UdpSocket, message: &ServerMessage) {
+        for client in self.clients.iter() {
+            self.send(socket, message, client);
+        }
+    }
+
+    fn handle_join(&mut self, socket: &UdpSocket, src: SocketAddr, join: JoinRequest) {
+        let JoinRequest { version, stream_port, pin, nickname, key_share } = join;
+        if version != PROTOCOL_VERSION {
+            let error = ServerMessage::Error {
+                code: ErrorCode::IncompatibleVersion,
+                message: format!("The presenter uses protocol version {}, this client uses version {}", PROTOCOL_VERSION, version),
+            };
+            self.send(socket, &error, &src);
+            return;
+        }
+
+        if self.banned.contains(&src.ip()) {
+            println!("Join from banned address {}", src);
+            let error = ServerMessage::Error {
+                code: ErrorCode::Banned,
+                message: "The presenter removed you from this session".to_string(),
+            };
+            self.send(socket, &error, &src);
+            return;
+        }
+
+        if self.pin.is_some() && pin != self.pin {
+            println!("Wrong PIN from {}", src);
+            let error = ServerMessage::Error {
+                code: ErrorCode::WrongPin,
+                message: "The session is protected and the PIN is wrong or missing".to_string(),
+            };
+            self.send(socket, &error, &src);
+            return;
+        }
+
+        //il nickname lo sceglie il client: niente stringhe vuote o lunghissime nella lista del presentatore
+        let nickname = nickname
+            .map(|nickname| nickname.trim().chars().take(MAX_NICKNAME_LEN).collect::<String>())
+            .filter(|nickname| !nickname.is_empty());
+
+        //senza la chiave pubblica del client non c'è modo di mandargli la chiave dello stream
+        if self.srtp_key.is_some() && !key_share.as_deref().is_some_and(srtp::is_valid_share) {
+            println!("Join from {} without a valid key share", src);
+            self.refuse_key_share(socket, &src);
+            return;
+        }
+
+        let client = SocketAddr::new(src.ip(), stream_port);
+        // chi è già stato ammesso non torna in sala d'attesa
+        if self.admission.is_some() && !self.clients.touch(&client) {
+            self.wait_for_admission(socket, src, client, nickname, key_share);
+            return;
+        }
+        self.admit(socket, src, client, nickname, key_share);
+    }
+
+    fn wait_for_admission(&mut self, socket: &UdpSocket, src: SocketAddr, client: SocketAddr, nickname: Option<String>, key_share: Option<String>) {
+        match self.pending.iter_mut().find(|pending| pending.client == client) {
+            // Join ripetuto mentre si aspetta la risposta del presentatore
+            Some(pending) => {
+                pending.last_seen = Instant::now();
+                pending.key_share = key_share;
+            },
+            None => {
+                println!("Client {} is waiting to be admitted", client);
+                self.notify_admission(AdmissionEvent::Requested(client, nickname.clone()));
+                self.pending.push(PendingJoin { src, client, nickname, key_share, last_seen: Instant::now() });
+            },
+        }
+        self.send(socket, &ServerMessage::Waiting, &src);
+    }
+
+    fn notify_admission(&self, event: AdmissionEvent) {
+        if let Some(admission) = &self.admission {
+            if let Err(e) = admission.try_send(event) {
+                println!("Failed to send admission event: {}", e);
+            }
+        }
+    }
+
+    /// Answer of the presenter to a client in the waiting room
+    fn handle_admission(&mut self, socket: &UdpSocket, client: SocketAddr, admitted: bool) {
+        let Some(index) = self.pending.iter().position(|pending| pending.client == client) else {
+            println!("Client {} is not waiting anymore", client);
+            return;
+        };
+        let pending = self.pending.remove(index);
+        if admitted {
+            self.admit(socket, pending.src, client, pending.nickname, pending.key_share);
+        } else {
+            println!("Client {} denied", client);
+            let error = ServerMessage::Error {
+                code: ErrorCode::Denied,
+                message: "The presenter did not admit you to the session".to_string(),
+            };
+            self.send(socket, &error, &pending.src);
+        }
+    }
+
+    fn refuse_key_share(&self, socket: &UdpSocket, src: &SocketAddr) {
+        let error = ServerMessage::Error {
+            code: ErrorCode::MalformedMessage,
+            message: "The session is encrypted and the request has no valid key share".to_string(),
+        };
+        self.send(socket, &error, src);
+    }
+
+    fn admit(&mut self, socket: &UdpSocket, src: SocketAddr, client: SocketAddr, nickname: Option<String>, key_share: Option<String>) {
+        //la chiave dello stream viaggia cifrata per questo client, mai in chiaro
+        let srtp_key = match &self.srtp_key {
+            Some(key) => match key_share.and_then(|share| key.wrap(&share)) {
+                Some(wrapped) => Some(wrapped),
+                None => {
+                    println!("Cannot encrypt the stream key for {}", src);
+                    self.refuse_key_share(socket, &src);
+                    return;
+                },
+            },
+            None => None,
+        };
+
+        // Un Join ripetuto dallo stesso client non lo aggiunge una seconda volta
+        let joined = self.clients.add(client);
+        self.clients.set_nickname(&client, nickname);
+
+        //Risponde al client con la sessione e l'indirizzo ip che verrà assegnato nel multiudp
+        let welcome = ServerMessage::Welcome(SessionInfo {
+            client_ip: src.ip().to_string(),
+            stream_port: client.port(),
+            leave_token: self.clients.token(&client).unwrap_or_default(),
+            srtp_key,
+            ..self.session.clone()
+        });
+        self.send(socket, &welcome, &src);
+
+        //chi si collega durante la pausa o con lo schermo oscurato deve saperlo subito
+        if self.paused {
+            self.send(socket, &ServerMessage::Event(StreamEvent::Paused), &src);
+        }
+        if self.blanked {
+            self.send(socket, &ServerMessage::Event(StreamEvent::Blanked), &src);
+        }
+
+        if joined {
+            // chi arriva tardi vede gli ultimi messaggi della chat
+            let history: Vec<ChatMessage> = {
+                let chat = self.chat.lock().unwrap();
+                chat[chat.len().saturating_sub(CHAT_HISTORY_ON_JOIN)..].to_vec()
+            };
+            for message in history {
+                self.send(socket, &ServerMessage::Chat(message), &src);
+            }
+
+            // Invia l'indirizzo del client al main tramite il canale
+            if let Err(e) = self.sender.send(ClientEvent::Joined(client)) {
+                println!("Failed to send client event: {}", e);
+            }
+            self.publish_viewers();
+        }
+    }
+
+    /// Stops sending the stream to `client` and tells it, with `ban` every later Join from its address is refused
+    fn remove_viewer(&mut self, socket: &UdpSocket, client: SocketAddr, ban: bool) {
+        if ban && !self.banned.contains(&client.ip()) {
+            self.banned.push(client.ip());
+        }
+        if !self.clients.remove(&client) {
+            println!("Cannot remove {}: not a viewer", client);
+            return;
+        }
+        println!("Client {} {}", client, if ban { "banned" } else { "kicked" });
+        self.send(socket, &ServerMessage::Removed { banned: ban }, &client);
+        if let Err(e) = self.sender.send(ClientEvent::Left(client)) {
+            println!("Failed to send client event: {}", e);
+        }
+        self.publish_viewers();
+    }
+
+    /// Message written by a viewer, relayed to the presenter and to every viewer (the author included)
+    fn handle_chat(&mut self, socket: &UdpSocket, src: SocketAddr, text: String) {
+        if !self.clients.touch(&src) {
+            println!("Chat message from unknown client {}", src);
+            return;
+        }
+        if !self.session.chat_enabled {
+            let error = ServerMessage::Error {
+                code: ErrorCode::ChatDisabled,
+                message: "The presenter disabled the chat".to_string(),
+            };
+            self.send(socket, &error, &src);
+            return;
+        }
+
+        let text: String = text.trim().chars().take(MAX_CHAT_LEN).collect();
+        if text.is_empty() {
+            return;
+        }
+        let author = self.clients.nickname(&src).unwrap_or_else(|| src.ip().to_string());
+        self.post_chat(socket, ChatMessage { author, from_presenter: false, text });
+    }
+
+    /// Click of a viewer on the video, shown to the presenter (and to everyone if the presenter shares the pointers)
+    fn handle_point(&mut self, src: SocketAddr, x: f64, y: f64) {
+        if !self.clients.touch(&src) {
+            println!("Pointer from unknown client {}", src);
+            return;
+        }
+        if !x.is_finite() || !y.is_finite() {
+            return;
+        }
+        let mark = PointerMark {
+            viewer: src,
+            name: self.clients.nickname(&src).unwrap_or_else(|| src.ip().to_string()),
+            x: x.clamp(0.0, 1.0),
+            y: y.clamp(0.0, 1.0),
+            at: Instant::now(),
+        };
+        if let Err(e) = self.sender.send(ClientEvent::Pointed(mark)) {
+            println!("Failed to send client event: {}", e);
+        }
+    }
+
+    /// A viewer asks to control the desktop, the presenter sees the request in the viewer panel
+    fn handle_control_request(&mut self, socket: &UdpSocket, src: SocketAddr) {
+        if !self.clients.touch(&src) {
+            println!("Control request from unknown client {}", src);
+            return;
+        }
+        if !self.session.remote_control {
+            let error = ServerMessage::Error {
+                code: ErrorCode::ControlUnavailable,
+                message: "The presenter does not allow remote control".to_string(),
+            };
+            self.send(socket, &error, &src);
+            return;
+        }
+        if self.clients.controller() == Some(src) {
+            // la risposta precedente può essere andata persa
+            self.send(socket, &ServerMessage::Control { granted: true }, &src);
+            return;
         }
+        println!("Client {} asks to control the desktop", src);
+        self.clients.set_control(&src, ControlStatus::Requested);
+        self.publish_viewers();
+    }
+
+    /// Answer of the presenter to a control request, the viewer in control before loses it
+    fn grant_control(&mut self, socket: &UdpSocket, client: SocketAddr) {
+        if !self.clients.iter().any(|viewer| *viewer == client) {
+            println!("Cannot give the control to {}: not a viewer", client);
+            return;
+        }
+        if let Some(controller) = self.clients.controller() {
+            self.revoke_control(socket, controller);
+        }
+        println!("Client {} controls the desktop", client);
+        self.clients.set_control(&client, ControlStatus::Granted);
+        self.send(socket, &ServerMessage::Control { granted: true }, &client);
+        if let Err(e) = self.sender.send(ClientEvent::ControlChanged(Some(client))) {
+            println!("Failed to send client event: {}", e);
+        }
+        self.publish_viewers();
+    }
+
+    /// Takes the control back from `client`, or refuses its request
+    fn revoke_control(&mut self, socket: &UdpSocket, client: SocketAddr) {
+        let was_controller = self.clients.controller() == Some(client);
+        if !self.clients.set_control(&client, ControlStatus::None) {
+            return;
+        }
+        if was_controller {
+            println!("Client {} does not control the desktop anymore", client);
+            if let Err(e) = self.sender.send(ClientEvent::ControlChanged(None)) {
+                println!("Failed to send client event: {}", e);
+            }
+        }
+        self.send(socket, &ServerMessage::Control { granted: false }, &client);
+        self.publish_viewers();
+    }
+
+    fn handle_input(&mut self, src: SocketAddr, event: InputEvent) {
+        //solo lo spettatore a cui il presentatore ha dato il controllo
+        if self.clients.controller() != Some(src) {
+            return;
+        }
+        self.clients.touch(&src);
+        if let Err(e) = self.sender.send(ClientEvent::Input(event)) {
+            println!("Failed to send client event: {}", e);
+        }
+    }
+
+    fn post_chat(&mut self, socket: &UdpSocket, message: ChatMessage) {
+        {
+            let mut chat = self.chat.lock().unwrap();
+            chat.push(message.clone());
+            if chat.len() > MAX_CHAT_HISTORY {
+                let excess = chat.len() - MAX_CHAT_HISTORY;
+                chat.drain(..excess);
+            }
+        }
+        self.notify_clients(socket, &ServerMessage::Chat(message));
+    }
+
+    /// Tells the clients on the LAN that this session exists, they list it in their session browser
+    fn announce(&self, socket: &UdpSocket) {
+        let announcement = ServerMessage::Announce(SessionAnnouncement {
+            version: PROTOCOL_VERSION,
+            session_name: self.session.session_name.clone(),
+            presenter_name: self.session.presenter_name.clone(),
+            viewers: self.clients.len(),
+            pin_required: self.pin.is_some(),
+        });
+        let broadcast = SocketAddr::from((Ipv4Addr::BROADCAST, ANNOUNCE_PORT));
+        if let Err(e) = socket.send_to(&protocol::encode(&announcement), broadcast) {
+            println!("Failed to announce the session: {}", e);
+        }
+    }
+
+    /// Removes the clients that stopped sending heartbeats, e.g. crashed or disconnected from the network,
+    /// and the ones that gave up waiting to be admitted
+    fn evict_silent_clients(&mut self) {
+        let (expired, waiting): (Vec<PendingJoin>, Vec<PendingJoin>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|pending| pending.last_seen.elapsed() > CLIENT_TIMEOUT);
+        self.pending = waiting;
+        for pending in expired {
+            println!("Client {} stopped waiting", pending.client);
+            self.notify_admission(AdmissionEvent::Withdrawn(pending.client));
+        }
+
+        for client in self.clients.remove_expired(CLIENT_TIMEOUT) {
+            println!("Client {} timed out", client);
+            if let Err(e) = self.sender.send(ClientEvent::Left(client)) {
+                println!("Failed to send client event: {}", e);
+            }
+        }
+        //aggiorna anche l'ultimo heartbeat dei client rimasti
+        self.publish_viewers();
+    }
+
+    fn handle_leave(&mut self, src: SocketAddr, token: u64) {
+        // Si toglie solo il client con lo stesso ip, la stessa porta e il token giusto
+        if !self.clients.remove_with_token(&src, token) {
+            println!("Ignored Leave from {}: unknown client or wrong token", src);
+            return;
+        }
+        if let Err(e) = self.sender.send(ClientEvent::Left(src)) {
+            println!("Failed to send client event: {}", e);
+        }
+        self.publish_viewers();
     }
 
     pub fn run_discovery_listener( &mut self,control_receiver:Receiver<ControlMessage>)  -> Result<(), Box<dyn Error>> {
-        let socket = UdpSocket::bind("0.0.0.0:9000")?;
-        socket.set_nonblocking(true)?;
+        let socket = self.socket.try_clone()?;
+        let mut last_eviction_check = Instant::now();
+        let mut last_announce: Option<Instant> = None;
                 loop {
                     match control_receiver.try_recv() {
                        
                         Ok(ControlMessage::Stop) => {
                             println!("Received STOP signal. Stopping discovery listener...");
+                            self.notify_clients(&socket, &ServerMessage::Event(StreamEvent::SessionEnded));
                             return Ok(());
-                        }         
+                        }
+
+                        Ok(ControlMessage::Notify(event)) => {
+                            match event {
+                                StreamEvent::Paused => self.paused = true,
+                                StreamEvent::Resumed => self.paused = false,
+                                StreamEvent::Blanked => self.blanked = true,
+                                StreamEvent::Unblanked => self.blanked = false,
+                                StreamEvent::ChatEnabled => self.session.chat_enabled = true,
+                                StreamEvent::ChatDisabled => self.session.chat_enabled = false,
+                                _ => {},
+                            }
+                            self.notify_clients(&socket, &ServerMessage::Event(event));
+                        }
+
+                        Ok(ControlMessage::Admit(client)) => {
+                            self.handle_admission(&socket, client, true);
+                        }
+
+                        Ok(ControlMessage::Deny(client)) => {
+                            self.handle_admission(&socket, client, false);
+                        }
+
+                        Ok(ControlMessage::Kick(client)) => {
+                            self.remove_viewer(&socket, client, false);
+                        }
+
+                        Ok(ControlMessage::Ban(client)) => {
+                            self.remove_viewer(&socket, client, true);
+                        }
+
+                        Ok(ControlMessage::GrantControl(client)) => {
+                            self.grant_control(&socket, client);
+                        }
+
+                        Ok(ControlMessage::RevokeControl(client)) => {
+                            match client.or_else(|| self.clients.controller()) {
+                                Some(client) => self.revoke_control(&socket, client),
+                                None => println!("Nobody controls the desktop"),
+                            }
+                        }
+
+                        Ok(ControlMessage::Chat(text)) => {
+                            let text: String = text.trim().chars().take(MAX_CHAT_LEN).collect();
+                            if !text.is_empty() {
+                                let author = self.session.presenter_name.clone();
+                                self.post_chat(&socket, ChatMessage { author, from_presenter: true, text });
+                            }
+                        }
      
                         Err(TryRecvError::Empty) => {
                             // non fare nulla
@@ -45,12 +541,22 @@ impl DiscoveryServer {
                     }
 
 
-                    let mut buf = [0; 1024];
+                    if last_eviction_check.elapsed() >= EVICTION_CHECK_INTERVAL {
+                        self.evict_silent_clients();
+                        last_eviction_check = Instant::now();
+                    }
+
+                    if last_announce.map_or(true, |sent| sent.elapsed() >= ANNOUNCE_INTERVAL) {
+                        self.announce(&socket);
+                        last_announce = Some(Instant::now());
+                    }
+
+                    let mut buf = [0; 4096]; // un messaggio della chat può superare 1 KB
                     let (amt, src) = match socket.recv_from(&mut buf) {
                         Ok(result) => result,
                         Err(e) => {
-                            if e.kind() == ErrorKind::WouldBlock {
-                                // If the socket would block (no data), we simply continue
+                            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+                                // nessun datagram entro LISTENER_POLL_INTERVAL
                                 continue;
                             } else {
                                 // Handle other errors (e.g., IO errors)
@@ -59,65 +565,46 @@ impl DiscoveryServer {
                         }}
                     };
         
-                    let received_message = String::from_utf8_lossy(&buf[..amt]);
-                    println!("Received message: '{}' from {}", received_message, src);
-        
-                    if received_message.trim() == "DISCOVERY" {
-                        //Risponde al client dandogli l'indirizzo ip che verrà assegnato nel multiudp
-                        let response = format!("{}", src.ip().to_string());
-        
-                        if let Err(e) = socket.send_to(response.as_bytes(), &src) {
-                            println!("Failed to send response: {}", e);
-                        } else {
-                            println!("Sent response '{}' to client {}", response, src);
+                    match protocol::decode::<ClientMessage>(&buf[..amt]) {
+                        Ok(ClientMessage::Join { version, stream_port, pin, nickname, key_share }) => {
+                            println!("Join request from {} (protocol version {})", src, version);
+                            self.handle_join(&socket, src, JoinRequest { version, stream_port, pin, nickname, key_share });
                         }
-        
-        
-                        // Aggiunge l'indirizzo del client alla lista formatta già correttamente per ScreenStreamer
-                        if !self.clients.is_empty() {
-                            self.clients.push_str(&format!(",{}", src.to_string()));
+                        Ok(ClientMessage::Heartbeat) => {
+                            if !self.clients.touch(&src) {
+                                println!("Heartbeat from unknown client {}", src);
+                            }
                         }
-                        else{
-                            self.clients.push_str(&format!("{}", src.to_string()));
+                        Ok(ClientMessage::Leave { token }) => {
+                            println!("Leave request from {}", src);
+                            self.handle_leave(src, token);
                         }
-        
-        
-                        // Invia l'indirizzo del client al main tramite il canale
-        
-                        //if let Err(e) = self.sender.send(src) {
-                        if let Err(e) = self.sender.send(self.clients.clone()) {
-                            println!("Failed to send client list: {}", e);
+                        Ok(ClientMessage::Chat { text }) => {
+                            self.handle_chat(&socket, src, text);
                         }
-                    }
-                    else if received_message.trim() == "DISCONNECT" { 
-                        
-                        let port = src.port().to_string();
-
-                        
-                        
-                        let clients_str: Vec<String> = self.clients.split(',')
-                                        .filter(|&s| {
-                                                
-                                                if let Some(port_str) = s.split(":").nth(1) {
-                                                    port_str != port 
-                                                } else {
-                                                    true // Mantieni il client se la porta non è presente (caso imprevisto)
-                                                }
-                                                
-                                            })
-                        .map(|s| s.to_string()) 
-                        .collect();
-                        
-                        self.clients = if clients_str.is_empty() {
-                            String::new() // Impostiamo una stringa vuota se la lista è vuota
-                        } else {
-                            clients_str.join(",") 
-                        };
-                        
-                    
-                        // Invia l'indirizzo del client al main tramite il canale
-                        if let Err(e) = self.sender.send(self.clients.clone()) {
-                            println!("Failed to send client list: {}", e);
+                        Ok(ClientMessage::Point { x, y }) => {
+                            self.handle_point(src, x, y);
+                        }
+                        Ok(ClientMessage::RequestControl) => {
+                            self.handle_control_request(&socket, src);
+                        }
+                        Ok(ClientMessage::ReleaseControl) => {
+                            self.revoke_control(&socket, src);
+                        }
+                        Ok(ClientMessage::Input(event)) => {
+                            self.handle_input(src, event);
+                        }
+                        Err(e) => {
+                            println!("Malformed message from {}: {}", src, e);
+                            //a qualunque altro datagram sulla porta non si risponde
+                            if !self.is_known_source(&src) && !looks_like_join(&buf[..amt]) {
+                                continue;
+                            }
+                            let error = ServerMessage::Error {
+                                code: ErrorCode::MalformedMessage,
+                                message: "The presenter could not understand the request, check the application version".to_string(),
+                            };
+                            self.send(&socket, &error, &src);
                         }
                     }
               
@@ -128,3 +615,17 @@ impl DiscoveryServer {
 
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_joins_are_recognized() {
+        assert!(looks_like_join(br#"{"type":"join","version":"two"}"#));
+        assert!(!looks_like_join(br#"{"type":"heartbeat","extra":1}"#));
+        assert!(!looks_like_join(b"GET / HTTP/1.1"));
+        assert!(!looks_like_join(b""));
+    }
+}
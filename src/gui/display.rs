@@ -1,9 +1,12 @@
 #[derive(Debug, Clone, Copy)]
 pub struct Display {
     pub id: u32, 
+    pub x: i32,
+    pub y: i32,
     pub width: u32,
     pub height: u32,
-    pub frequency: f32
+    pub frequency: f32,
+    pub position_known: bool, // il portale non dà la posizione delle finestre
 }
 impl std::fmt::Display for Display {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -1,10 +1,12 @@
-use iced::{ keyboard::{Event::KeyPressed, Key}, widget::image::Handle};
+use iced::{ keyboard::{Event::KeyPressed, Key, key::Named}, widget::image::Handle};
 use selector_draw::MyCanvas;
+use pointer_draw::PointerCanvas;
+use video_view::{VideoAction, VideoView, Zoom};
 use display::Display;
 use icon::Icon;
 use std::sync::{Arc, Mutex};
 use cropper::dimension_to_crop;
-use iced::widget::{self, button, center, container, pick_list, Canvas, MouseArea};
+use iced::widget::{self, button, center, checkbox, container, pick_list, scrollable, Canvas, MouseArea};
 use std::time::Duration;
 use async_std::task::sleep;
 
@@ -12,7 +14,7 @@ use iced::{
     touch::Event::FingerMoved,
     event::{self, Event, Status}, 
     mouse::{self, Event::{ButtonPressed, ButtonReleased, CursorMoved}},
-     Element, Point, Theme};
+     Element, Point, Theme, Vector};
 use iced::widget::{
      column, row, text, text_input
 };
@@ -34,25 +36,37 @@ use ashpd::{
 use screenshots::Screen;
 
 
-use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::path::PathBuf;
 use get_if_addrs::get_if_addrs;
 
 use crate::streamer::client::StreamerClient;
-use crate::connection::client::DiscoveryClient;
+use crate::streamer::config::{OutputResolution, RemoteControlMode, SpeedPreset, StreamConfig};
+use crate::streamer::capture::{self, CaptureKind, CaptureSource, FileSource, TestSource};
+use crate::streamer::placeholder::{Placeholder, PlaceholderKind};
+use crate::streamer::error::ServerError;
+use crate::streamer::pointer::PointerMark;
+use crate::connection::client::{self as discovery, DiscoveryClient, StreamStatus};
+use crate::connection::protocol::{ChatMessage, SessionInfo, StreamEvent, ANNOUNCE_PORT, HEARTBEAT_INTERVAL, MAX_CHAT_LEN};
+use crate::connection::browser::{DiscoveredSession, SessionBrowser};
+use crate::connection::registry::{AdmissionEvent, ControlStatus, ViewerInfo};
 use crate::StreamerState;
 
 use iced::application;
+use iced::futures::StreamExt;
 
-use super::{cropper, display, icon, selector_draw};
+use super::{cropper, display, icon, pointer_draw, selector_draw, video_view};
 
 pub fn run_iced() -> iced::Result {
-    iced::application("Ferris - Iced", ScreenSharer::update, ScreenSharer::view)
+    iced::application(ScreenSharer::title, ScreenSharer::update, ScreenSharer::view)
         .style(ScreenSharer::style).subscription(ScreenSharer::subscription).window(iced::window::Settings {
             //decorations: false,  to make window borderless
             ..Default::default()
         }).transparent(true)
         .theme(|_| Theme::TokyoNight)
-        .run()
+        .run_with(ScreenSharer::new)
 }
 
 #[derive(Debug, Clone)]
@@ -71,15 +85,44 @@ enum StreamingState{
 enum ConnectionResult {
     None, 
     Success,
-    Failed,
+    Failed(String),
+    Closed(String), // la sessione è finita o il presentatore ci ha tolto
 }
 
+const FRAMERATES: [i32; 4] = [15, 24, 30, 60];
+//ogni quanto si prende l'ultimo frame ricevuto, abbastanza per uno stream a 60 fps
+const FRAME_POLL_INTERVAL: Duration = Duration::from_millis(16);
+//le icone sono dentro l'eseguibile, che così parte da qualsiasi cartella
+const BACK_ICON: &[u8] = include_bytes!("../images/left.png");
+const CONNECTED_ICON: &[u8] = include_bytes!("../images/checked.png");
+const FAILED_ICON: &[u8] = include_bytes!("../images/cross.png");
+
 struct ScreenSharer {
     input_value_client: String,
     ips: String,
     streamer_client: Option<StreamerClient>,
     streamer_state: Option<StreamerState>,
     connection_client: Option<Arc<Mutex<DiscoveryClient>>>,
+    connection_thread: Option<JoinHandle<Result<(StreamerClient, Arc<Mutex<DiscoveryClient>>), String>>>,
+    waiting_admission: Arc<AtomicBool>,
+    join_requests: Vec<(SocketAddr, Option<String>)>,
+    nickname_input: String,
+    watching: Option<SessionInfo>, // sessione che il client sta guardando
+    viewers: Vec<ViewerInfo>,
+    chat_messages: Vec<ChatMessage>, // cronologia della sessione, del presentatore o del client
+    chat_input: String,
+    pointers: Vec<(PointerMark, Option<(i32, i32)>)>, // segni degli spettatori e punto dello schermo corrispondente
+    stream_size: Option<(i32, i32)>,
+    pointing: bool, // il client manda al presentatore i click sul video
+    video_frame: Option<Handle>, // ultimo frame dello stream ricevuto
+    video_size: (u32, u32),
+    zoom: Zoom, // solo per questo spettatore, gli altri non ne risentono
+    pan: Vector,
+    fullscreen: bool,
+    control_requested: bool, // il client aspetta che il presentatore gli dia il controllo
+    in_control: bool,
+    session_browser: Option<SessionBrowser>,
+    discovered_sessions: Vec<DiscoveredSession>,
     mouse_point: Point,
     first_point: Option<Point>,
     second_point: Option<Point>,
@@ -93,8 +136,28 @@ struct ScreenSharer {
     connection_result: ConnectionResult,
     is_recording: bool,
     can_start_stream: bool,
+    stream_config: StreamConfig,
+    capture_kind: CaptureKind,
+    media_file: Option<PathBuf>,
+    placeholder_kind: PlaceholderKind,
+    placeholder_image: Option<PathBuf>,
+    bitrate_input: String,
+    keyframe_input: String,
+    discovery_port_input: String,
+    rtp_port_input: String,
+    client_port: u16,       // 0 = porta scelta dal sistema
+    client_port_input: String,
+    pin_input: String,
+    client_pin_input: String,
+    system_audio_muted: bool,
+    microphone_muted: bool,
+    receive_audio: bool,
+    client_muted: bool,
+    stream_status: StreamStatus,
     #[cfg(target_os = "linux")]
     valnode:u32,
+    #[cfg(target_os = "linux")]
+    x11_fallback: bool,
 }
 
 impl Default for ScreenSharer {
@@ -106,17 +169,42 @@ impl Default for ScreenSharer {
             .enumerate() // Aggiunge un contatore da 0 a n
             .map(|(index, screen)| Display {
                 id: index as u32, // Usa l'indice come id
+                x: screen.display_info.x,
+                y: screen.display_info.y,
                 width: screen.display_info.width,
                 height: screen.display_info.height,
                 frequency: screen.display_info.frequency,
+                position_known: true,
             })
             .collect();
 
+        let stream_config = StreamConfig::default();
+
         Self{
             input_value_client: "".to_string(),
             ips: "".to_string(),
             streamer_client: None,
             connection_client: None,
+            connection_thread: None,
+            waiting_admission: Arc::new(AtomicBool::new(false)),
+            join_requests: Vec::new(),
+            nickname_input: crate::user_name(),
+            watching: None,
+            viewers: Vec::new(),
+            chat_messages: Vec::new(),
+            chat_input: String::new(),
+            pointers: Vec::new(),
+            stream_size: None,
+            pointing: false,
+            video_frame: None,
+            video_size: (0, 0),
+            zoom: Zoom::Fit,
+            pan: Vector::ZERO,
+            fullscreen: false,
+            control_requested: false,
+            in_control: false,
+            session_browser: None,
+            discovered_sessions: Vec::new(),
             streamer_state: None,
             mouse_point: Point::ORIGIN,
             first_point: None,
@@ -131,16 +219,38 @@ impl Default for ScreenSharer {
             connection_result: ConnectionResult::None,
             is_recording: false,
             can_start_stream:true,
+            bitrate_input: stream_config.bitrate.to_string(),
+            keyframe_input: stream_config.keyframe_interval.to_string(),
+            discovery_port_input: stream_config.discovery_port.to_string(),
+            rtp_port_input: String::new(),
+            client_port: 0,
+            client_port_input: String::new(),
+            pin_input: String::new(),
+            client_pin_input: String::new(),
+            stream_config,
+            capture_kind: CaptureKind::Screen,
+            media_file: None,
+            placeholder_kind: PlaceholderKind::BlankImage,
+            placeholder_image: None,
+            system_audio_muted: false,
+            microphone_muted: false,
+            receive_audio: true,
+            client_muted: false,
+            stream_status: StreamStatus::default(),
             #[cfg(target_os = "linux")]
             valnode: 0,
+            #[cfg(target_os = "linux")]
+            x11_fallback: false,
         }
     }
 }
 
 #[derive(Debug, Clone)]
 enum Message {
+    StartStreamPressed,
     StreamerPressed,
     ClientPressed,
+    CheckConnection,
     StopStreamerPressed,
      StreamerStopped,
     StopClientPressed,
@@ -159,9 +269,57 @@ enum Message {
     PauseStreaming,
     ResumeStreaming,
     Connection,
+    ChangeBitrate(String),
+    ChangeFramerate(i32),
+    ChangeKeyframeInterval(String),
+    ChangeDiscoveryPort(String),
+    ChangeRtpPort(String),
+    ChangeClientPort(String),
+    ChangePin(String),
+    ChangeClientPin(String),
+    ToggleWaitingRoom(bool),
+    ToggleConfidential(bool),
+    ChangeSessionName(String),
+    ChangePresenterName(String),
+    ChangeNickname(String),
+    AdmissionUpdate(AdmissionEvent),
+    AnswerJoinRequest(SocketAddr, bool),
+    RefreshViewers,
+    RemoveViewer(SocketAddr, bool),
+    ChangeChatInput(String),
+    SendChat,
+    ToggleChat(bool),
+    ToggleSharePointers(bool),
+    TogglePointing(bool),
+    ToggleRemoteControl(bool),
+    GrantControl(SocketAddr),
+    RevokeControl(Option<SocketAddr>),
+    RequestControl,
+    ReleaseControl,
+    ChangePreset(SpeedPreset),
+    ChangeResolution(OutputResolution),
+    ChangeCaptureKind(CaptureKind),
+    ChangePlaceholderKind(PlaceholderKind),
+    ToggleSystemAudio(bool),
+    ToggleMicrophone(bool),
+    MuteSystemAudio(bool),
+    MuteMicrophone(bool),
+    ToggleReceiveAudio(bool),
+    MuteClientAudio(bool),
+    CheckStreamState,
+    NextFrame,
+    Video(VideoAction),
+    ChangeZoom(Zoom),
+    ZoomStep(i32),
+    ToggleFullscreen,
+    ExitFullscreen,
+    RefreshSessions,
+    JoinSession(SocketAddr),
     #[cfg(target_os = "linux")]
     GotValNode(Result<Display,u32>),
     #[cfg(target_os = "linux")]
+    PortalChecked(bool),
+    #[cfg(target_os = "linux")]
     RetIdPipewire,
     #[cfg(target_os = "linux")]
     DoNothing
@@ -169,12 +327,347 @@ enum Message {
 
 impl ScreenSharer {
 
+    /// The client shows what it is watching
+    fn title(&self) -> String {
+        match (&self.connection_result, &self.watching) {
+            (ConnectionResult::Success, Some(session)) => format!("{} - {} | Ferris", session.session_name, session.presenter_name),
+            _ => "Ferris - Iced".to_string(),
+        }
+    }
+
+    fn new() -> (Self, Task<Message>) {
+        cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                //si controlla subito se c'è il portale, altrimenti si passa a ximagesrc
+                (Self::default(), Task::perform(portal_available(), Message::PortalChecked))
+            } else {
+                (Self::default(), Task::none())
+            }
+        }
+    }
 
     fn update(&mut self, message: Message) ->Task<Message> {
         match message {
             Message::ChangeSelectedScreen(display) => {
                 self.selected_screen = Some(display);
             },
+            Message::ChangeBitrate(value) => {
+                //il valore viene usato solo se è un numero valido, altrimenti resta quello precedente
+                if let Ok(bitrate) = value.trim().parse::<u32>() {
+                    if bitrate > 0 {
+                        self.stream_config.bitrate = bitrate;
+                    }
+                }
+                self.bitrate_input = value;
+            },
+            Message::ChangeFramerate(framerate) => {
+                self.stream_config.max_framerate = framerate;
+            },
+            Message::ChangeKeyframeInterval(value) => {
+                if let Ok(interval) = value.trim().parse::<u32>() {
+                    if interval > 0 {
+                        self.stream_config.keyframe_interval = interval;
+                    }
+                }
+                self.keyframe_input = value;
+            },
+            Message::ChangeDiscoveryPort(value) => {
+                if let Ok(port) = value.trim().parse::<u16>() {
+                    //la porta degli annunci è già usata da tutte le sessioni
+                    if port > 0 && port != ANNOUNCE_PORT {
+                        self.stream_config.discovery_port = port;
+                    }
+                }
+                self.discovery_port_input = value;
+            },
+            Message::ChangeRtpPort(value) => {
+                //vuoto = porta scelta dal sistema
+                match value.trim() {
+                    "" => self.stream_config.rtp_port = 0,
+                    port => if let Ok(port) = port.parse::<u16>() {
+                        self.stream_config.rtp_port = port;
+                    },
+                }
+                self.rtp_port_input = value;
+            },
+            Message::ChangeClientPort(value) => {
+                match value.trim() {
+                    "" => self.client_port = 0,
+                    port => if let Ok(port) = port.parse::<u16>() {
+                        self.client_port = port;
+                    },
+                }
+                self.client_port_input = value;
+            },
+            Message::ChangePin(value) => {
+                //un PIN vuoto lascia la sessione aperta a tutti
+                self.stream_config.pin = Some(value.trim().to_string()).filter(|pin| !pin.is_empty());
+                self.pin_input = value;
+            },
+            Message::ChangeClientPin(value) => {
+                self.client_pin_input = value;
+            },
+            Message::ToggleWaitingRoom(enabled) => {
+                self.stream_config.waiting_room = enabled;
+            },
+            Message::ToggleConfidential(enabled) => {
+                self.stream_config.confidential = enabled;
+            },
+            Message::ChangeSessionName(name) => {
+                self.stream_config.session_name = name;
+            },
+            Message::ChangePresenterName(name) => {
+                self.stream_config.presenter_name = name;
+            },
+            Message::ChangeNickname(nickname) => {
+                self.nickname_input = nickname;
+            },
+            Message::AdmissionUpdate(event) => {
+                match event {
+                    AdmissionEvent::Requested(client, nickname) => if !self.join_requests.iter().any(|(request, _)| *request == client) {
+                        self.join_requests.push((client, nickname));
+                    },
+                    AdmissionEvent::Withdrawn(client) => self.join_requests.retain(|(request, _)| *request != client),
+                }
+            },
+            Message::RefreshViewers => {
+                if let Some(state) = &self.streamer_state {
+                    self.viewers = state.viewers.lock().expect("errore getting viewers").clone();
+                    self.chat_messages = state.chat.lock().expect("errore getting chat").clone();
+                    let streamer = state.streamer_arc.lock().expect("errore getting arc");
+                    self.pointers = streamer.pointers().into_iter()
+                        .map(|mark| {
+                            let on_screen = streamer.pointer_on_screen(&mark);
+                            (mark, on_screen)
+                        })
+                        .collect();
+                    self.stream_size = streamer.output_size();
+                    //finito il file da trasmettere si chiude la sessione come con il pulsante di stop
+                    if streamer.source_ended() {
+                        println!("The streamed source ended, stopping the session");
+                        return Task::perform(async {}, |_| Message::StopStreamerPressed);
+                    }
+                }
+            },
+            Message::ChangeChatInput(value) => {
+                self.chat_input = value.chars().take(MAX_CHAT_LEN).collect();
+            },
+            Message::SendChat => {
+                let text = std::mem::take(&mut self.chat_input);
+                if text.trim().is_empty() {
+                    return Task::none();
+                }
+                //il messaggio compare nella cronologia quando il server lo rimanda a tutti
+                match (&self.streamer_state, &self.connection_client) {
+                    (Some(state), _) => crate::send_chat(state, text),
+                    (None, Some(discovery_client)) => {
+                        if let Err(e) = discovery_client.lock().expect("errore getting arc").send_chat(&text) {
+                            println!("Failed to send the chat message: {}", e);
+                        }
+                    },
+                    (None, None) => println!("Cannot send a chat message outside of a session"),
+                }
+            },
+            Message::ToggleSharePointers(shared) => {
+                self.stream_config.share_pointers = shared;
+                if let Some(state) = &self.streamer_state {
+                    state.streamer_arc.lock().expect("errore getting arc").set_pointers_shared(shared);
+                }
+            },
+            Message::TogglePointing(pointing) => {
+                self.pointing = pointing;
+            },
+            Message::NextFrame => {
+                if let Some(frame) = self.streamer_client.as_ref().and_then(StreamerClient::take_frame) {
+                    self.video_size = (frame.width, frame.height);
+                    self.video_frame = Some(Handle::from_rgba(frame.width, frame.height, frame.pixels));
+                }
+            },
+            Message::Video(action) => match action {
+                VideoAction::Input(input) => {
+                    if let (true, Some(client)) = (self.in_control, &self.streamer_client) {
+                        client.send_input(input);
+                    }
+                },
+                //con un clic sinistro si indica un punto al presentatore
+                VideoAction::Clicked(x, y) => {
+                    if let (true, Some(discovery_client)) = (self.pointing, &self.connection_client) {
+                        if let Err(e) = discovery_client.lock().expect("errore getting arc").send_point(x, y) {
+                            println!("Failed to send the pointer: {}", e);
+                        }
+                    }
+                },
+                VideoAction::Pan(pan) => {
+                    self.pan = pan;
+                },
+                VideoAction::Zoom(zoom) => {
+                    self.zoom = zoom;
+                    self.pan = Vector::ZERO;
+                },
+            },
+            Message::ChangeZoom(zoom) => {
+                self.zoom = zoom;
+                self.pan = Vector::ZERO;
+            },
+            Message::ZoomStep(steps) => {
+                self.zoom = self.zoom.step(steps);
+                self.pan = Vector::ZERO;
+            },
+            Message::ToggleFullscreen => {
+                self.fullscreen = !self.fullscreen;
+                let mode = match self.fullscreen {
+                    true => window::Mode::Fullscreen,
+                    false => window::Mode::Windowed,
+                };
+                return window::get_oldest().and_then(move |id| window::change_mode(id, mode));
+            },
+            Message::ExitFullscreen => {
+                if self.fullscreen {
+                    self.fullscreen = false;
+                    return window::get_oldest().and_then(|id| window::change_mode(id, window::Mode::Windowed));
+                }
+            },
+            Message::ToggleRemoteControl(allowed) => {
+                self.stream_config.remote_control = match allowed {
+                    true => RemoteControlMode::Enabled,
+                    false => RemoteControlMode::Disabled,
+                };
+            },
+            Message::GrantControl(client) => {
+                if let Some(state) = &self.streamer_state {
+                    crate::grant_control(state, client);
+                }
+            },
+            Message::RevokeControl(client) => {
+                if let Some(state) = &self.streamer_state {
+                    crate::revoke_control(state, client);
+                }
+            },
+            Message::RequestControl | Message::ReleaseControl => {
+                if let Some(ref discovery_client) = self.connection_client {
+                    let discovery_client = discovery_client.lock().expect("errore getting arc");
+                    let result = match message {
+                        Message::RequestControl => discovery_client.request_control(),
+                        _ => discovery_client.release_control(),
+                    };
+                    if let Err(e) = result {
+                        println!("Failed to send the control request: {}", e);
+                    }
+                    self.control_requested = discovery_client.control_requested();
+                    self.in_control = discovery_client.in_control();
+                }
+            },
+            Message::ToggleChat(enabled) => {
+                self.stream_config.chat_enabled = enabled;
+                if let Some(state) = &self.streamer_state {
+                    crate::set_chat_enabled(state, enabled);
+                }
+            },
+            Message::RemoveViewer(client, ban) => {
+                if let Some(state) = &self.streamer_state {
+                    crate::remove_viewer(state, client, ban);
+                }
+            },
+            Message::AnswerJoinRequest(client, admitted) => {
+                self.join_requests.retain(|(request, _)| *request != client);
+                if let Some(state) = &self.streamer_state {
+                    crate::answer_join_request(state, client, admitted);
+                }
+            },
+            Message::ChangePreset(preset) => {
+                self.stream_config.preset = preset;
+            },
+            Message::ChangeResolution(resolution) => {
+                self.stream_config.output_resolution = resolution;
+            },
+            Message::ChangeCaptureKind(kind) => {
+                if kind == CaptureKind::MediaFile {
+                    self.media_file = rfd::FileDialog::new()
+                        .set_title("Choose the video to stream")
+                        .pick_file();
+                    if self.media_file.is_none() {
+                        //nessun file scelto, si resta sulla sorgente precedente
+                        return Task::none();
+                    }
+                }
+                self.capture_kind = kind;
+            },
+            Message::ChangePlaceholderKind(kind) => {
+                if kind == PlaceholderKind::CustomImage {
+                    self.placeholder_image = rfd::FileDialog::new()
+                        .set_title("Choose the image shown instead of the screen")
+                        .add_filter("Images", &["png", "jpg", "jpeg", "bmp", "gif"])
+                        .pick_file();
+                    if self.placeholder_image.is_none() {
+                        return Task::none();
+                    }
+                }
+                self.placeholder_kind = kind;
+                //se lo schermo è già oscurato si cambia subito quello che vedono i client
+                if self.is_blank {
+                    return Task::perform(async { }, |_| Message::SetBlankScreen);
+                }
+            },
+            Message::ToggleSystemAudio(enabled) => {
+                self.stream_config.audio.system_audio = enabled;
+            },
+            Message::ToggleMicrophone(enabled) => {
+                self.stream_config.audio.microphone = enabled;
+            },
+            Message::MuteSystemAudio(muted) => {
+                if let Some(ref state) = self.streamer_state {
+                    if state.streamer_arc.lock().expect("errore getting arc").set_system_audio_muted(muted) {
+                        self.system_audio_muted = muted;
+                    }
+                }
+            },
+            Message::MuteMicrophone(muted) => {
+                if let Some(ref state) = self.streamer_state {
+                    if state.streamer_arc.lock().expect("errore getting arc").set_microphone_muted(muted) {
+                        self.microphone_muted = muted;
+                    }
+                }
+            },
+            Message::ToggleReceiveAudio(enabled) => {
+                self.receive_audio = enabled;
+            },
+            Message::MuteClientAudio(muted) => {
+                if let Some(ref client) = self.streamer_client {
+                    client.set_muted(muted);
+                    self.client_muted = muted;
+                }
+            },
+            Message::CheckStreamState => {
+                let mut removed = None;
+                if let Some(ref discovery_client) = self.connection_client {
+                    let discovery_client = discovery_client.lock().expect("errore getting arc");
+                    self.stream_status = discovery_client.stream_status();
+                    self.chat_messages = discovery_client.chat();
+                    removed = discovery_client.removed();
+                    self.control_requested = discovery_client.control_requested();
+                    self.in_control = discovery_client.in_control();
+                }
+                let reason = match removed {
+                    Some(true) => Some("The presenter banned you from the session"),
+                    Some(false) => Some("The presenter removed you from the session"),
+                    None if self.stream_status.ended => Some("The presenter ended the session"),
+                    None => None,
+                };
+                //si chiude il client come se l'utente avesse premuto stop
+                if let Some(reason) = reason {
+                    let task = self.update(Message::StopClientPressed);
+                    self.connection_result = ConnectionResult::Closed(reason.to_string());
+                    return task;
+                }
+            },
+            Message::StartStreamPressed => {
+                //solo la cattura dello schermo su linux passa dal portale per scegliere il nodo pipewire
+                #[cfg(target_os = "linux")]
+                if self.capture_kind == CaptureKind::Screen && !self.x11_fallback {
+                    return self.update(Message::RetIdPipewire);
+                }
+                return self.update(Message::StreamerPressed);
+            },
             Message::Connection => {
                     self.connection_waiting = true;
                     
@@ -183,18 +676,43 @@ impl ScreenSharer {
                     );
             },
             Message::ClientPressed => {
-                    let ip:IpAddr=self.input_value_client.clone().trim().parse::<IpAddr>().unwrap();
-                    let client_handle = std::thread::spawn(move || {
-                        crate::start_client(ip).unwrap() // in futuro maneggia errori
-                    });
-
-                    if let Ok((client, discovery_client)) = client_handle.join() {
-                        self.streamer_client = Some(client);
-                        self.connection_client = Some(discovery_client);
-                        self.connection_result = ConnectionResult::Success;
+                    //JoinSession arriva qui senza passare dal controllo del campo indirizzo
+                    let Some(server_addr) = discovery::parse_server_address(&self.input_value_client) else {
+                        self.connection_result = ConnectionResult::Failed(format!("Invalid server address '{}'", self.input_value_client));
+                        self.connection_waiting = false;
+                        return Task::none();
+                    };
+                    let local_port = self.client_port;
+                    let pin = Some(self.client_pin_input.trim().to_string()).filter(|pin| !pin.is_empty());
+                    let nickname = Some(self.nickname_input.trim().to_string()).filter(|nickname| !nickname.is_empty());
+                    let with_audio = self.receive_audio;
+                    let waiting = Arc::clone(&self.waiting_admission);
+                    waiting.store(false, Ordering::Relaxed);
+                    //con la sala d'attesa la risposta può arrivare molto dopo, il thread viene controllato da CheckConnection
+                    self.connection_thread = Some(std::thread::spawn(move || {
+                        crate::start_client(server_addr, local_port, pin, nickname, with_audio, &waiting).map_err(|e| e.to_string())
+                    }));
+            }
+            Message::CheckConnection => {
+                    if !self.connection_thread.as_ref().is_some_and(|handle| handle.is_finished()) {
+                        return Task::none();
                     }
-                    else {
-                        self.connection_result = ConnectionResult::Failed;
+                    let client_handle = self.connection_thread.take().unwrap();
+
+                    match client_handle.join() {
+                        Ok(Ok((client, discovery_client))) => {
+                            self.streamer_client = Some(client);
+                            self.watching = discovery_client.lock().expect("errore getting arc").session().cloned();
+                            self.connection_client = Some(discovery_client);
+                            self.connection_result = ConnectionResult::Success;
+                        },
+                        Ok(Err(e)) => {
+                            println!("Error in connecting: {}", e);
+                            self.connection_result = ConnectionResult::Failed(e);
+                        },
+                        Err(_) => {
+                            self.connection_result = ConnectionResult::Failed("The connection thread panicked".to_string());
+                        },
                     }
                     
                     self.connection_waiting = false;
@@ -255,7 +773,21 @@ impl ScreenSharer {
                 }
                 self.connection_result = ConnectionResult::None;
                 self.is_recording = false;
-                
+                self.client_muted = false;
+                self.stream_status = StreamStatus::default();
+                self.watching = None;
+                self.chat_messages.clear();
+                self.chat_input.clear();
+                self.control_requested = false;
+                self.in_control = false;
+                self.video_frame = None;
+                self.video_size = (0, 0);
+                self.zoom = Zoom::Fit;
+                self.pan = Vector::ZERO;
+                if self.fullscreen {
+                    self.fullscreen = false;
+                    return window::get_oldest().and_then(|id| window::change_mode(id, window::Mode::Windowed));
+                }
             }
             Message::StreamerPressed => {
                 let crop = dimension_to_crop(self.first_point, self.second_point, self.selected_screen);
@@ -277,25 +809,36 @@ impl ScreenSharer {
                     }
                 }
                 
-                match self.selected_screen {
-                    Some(_) => {
-                        let id_screen: usize = self.selected_screen.unwrap().id as usize;
+                let source: Option<Box<dyn CaptureSource>> = match self.capture_kind {
+                    CaptureKind::Screen => self.selected_screen
+                        .and_then(|screen| self.screen_source(screen).map_err(|e| println!("{}", e)).ok()),
+                    CaptureKind::TestPattern => Some(Box::new(TestSource::default())),
+                    CaptureKind::MediaFile => self.media_file.clone()
+                        .map(|path| Box::new(FileSource { path }) as Box<dyn CaptureSource>),
+                };
+
+                match source {
+                    Some(source) => {
+                        let config = self.stream_config.clone();
                                 // Start the streamer in a separate thread and store the result in self.streamer_state.
                         let streamer_state = std::thread::spawn(move || {
-                            crate::start_streamer(crop, id_screen).unwrap()
+                            crate::start_streamer(crop, source, config).map_err(|e| e.to_string())
                         });
-                        if let Ok(streamer) = streamer_state.join() {
-                            self.streamer_state = Some(streamer);
-                            println!("Streamer started.");
-                            self.streaming_state = StreamingState::Play;
+                        match streamer_state.join() {
+                            Ok(Ok(streamer)) => {
+                                self.streamer_state = Some(streamer);
+                                self.system_audio_muted = false;
+                                self.microphone_muted = false;
+                                println!("Streamer started.");
+                                self.streaming_state = StreamingState::Play;
+                            },
+                            // es. la porta scelta è già usata da un'altra sessione
+                            Ok(Err(e)) => println!("Streamer DID NOT started: {}", e),
+                            Err(_) => println!("Streamer DID NOT started."),
                         }
-                        else {
-                            println!("Streamer DID NOT started.");
-                            
-                        } 
                     },
                     None => {
-                        println!("Cannot start the stream until you choose the screen"); 
+                        println!("Cannot start the stream until you choose the screen or the file to stream"); 
                     },
                 }
                 self.can_start_stream=true;
@@ -313,11 +856,26 @@ impl ScreenSharer {
             #[cfg(target_os = "linux")]
             Message::DoNothing => {
                 
+            }
+            #[cfg(target_os = "linux")]
+            Message::PortalChecked(available) => {
+                self.x11_fallback = !available && capture::x11_available();
+                if self.x11_fallback {
+                    println!("No screencast portal found, capturing the screen with ximagesrc");
+                }
             }
             #[cfg(target_os = "linux")]
             Message::GotValNode(r)=>{
                 let pipe_res=match r {
                     Ok(dis)=> dis,
+                    Err(e) if e == PORTAL_UNAVAILABLE && capture::x11_available() => {
+                        //senza portale si cattura direttamente il monitor scelto dal server X
+                        self.x11_fallback = true;
+                        if self.selected_screen.is_none() {
+                            self.selected_screen = self.available_display.first().copied();
+                        }
+                        return self.update(Message::StreamerPressed);
+                    },
                     Err(e) if  e==2 =>  {
                         self.can_start_stream=true;
                         return Task::perform (async { },
@@ -345,8 +903,13 @@ impl ScreenSharer {
             }
             Message::StopStreamerPressed => {
                 let  state =self.streamer_state.as_ref().unwrap();
+                //durante la pausa i client vedono solo il frame in pausa, si riprende per mostrare l'immagine finale
+                let is_paused = state.streamer_arc.lock().expect("errore getting arc").is_paused();
+                if is_paused {
+                    crate::resume_streamer(state);
+                }
                 let arc_streamer_state =state.streamer_arc.lock();
-                let res_img_stream=arc_streamer_state.expect("errore frov").share_static_image_end("end_stream_ai.png".to_string());
+                let res_img_stream=arc_streamer_state.expect("errore frov").show_placeholder(Some(&Placeholder::end_of_stream()));
                 match res_img_stream {
                     Ok(())=> { println!("Streaming end stream image");
                     if let Some(state) = self.streamer_state.take() {
@@ -359,6 +922,11 @@ impl ScreenSharer {
                         }, |_| Message::StreamerStopped);
                         self.can_start_stream = false;
                         self.streaming_state = StreamingState::Starting;
+                        self.join_requests.clear();
+                        self.viewers.clear();
+                        self.chat_messages.clear();
+                        self.chat_input.clear();
+                        self.pointers.clear();
                         
                        
                         return Task::batch(vec![
@@ -392,6 +960,7 @@ impl ScreenSharer {
                 self.second_point = Some(self.mouse_point);
                 println!("New Points saved: {}, {}", self.first_point.unwrap(), self.second_point.unwrap());
                 self.is_selecting_area = false; 
+                self.apply_capture_region();
                 return Task::batch(vec![
                     window::get_latest().and_then(iced::window::toggle_maximize),  
                 ]);        
@@ -401,6 +970,7 @@ impl ScreenSharer {
                     Some(_) => {
                         self.first_point = None;
                         self.second_point = None;
+                        self.apply_capture_region();
                     },
                     None => {
                 return Task::batch(vec![
@@ -414,28 +984,54 @@ impl ScreenSharer {
                 self.is_selecting_area = true;
             },
             Message::ChangeApplicationState(state) => {
+                //le sessioni sulla rete si cercano solo finché si è nella schermata del client
+                match state {
+                    ApplicationState::Client => if self.session_browser.is_none() {
+                        match SessionBrowser::start() {
+                            Ok(browser) => self.session_browser = Some(browser),
+                            Err(e) => println!("Cannot look for sessions on the network: {}", e),
+                        }
+                    },
+                    _ => {
+                        self.session_browser = None;
+                        self.discovered_sessions.clear();
+                    },
+                }
                 self.application_state = state;
             },
+            Message::RefreshSessions => {
+                if let Some(browser) = &self.session_browser {
+                    self.discovered_sessions = browser.sessions();
+                }
+            },
+            Message::JoinSession(address) => {
+                self.input_value_client = address.to_string();
+                return self.update(Message::Connection);
+            },
             Message::SetBlankScreen => {
                 let  state =self.streamer_state.as_ref().unwrap();
                     let arc_streamer_state =state.streamer_arc.lock();
               
-                    let streamres=arc_streamer_state.expect("errore getting  arc").share_static_image_end("blank.png".to_string());
+                    let streamres=arc_streamer_state.expect("errore getting  arc").show_placeholder(Some(&self.placeholder()));
                     match streamres {
-                        Ok(())=> self.is_blank=true,
+                        Ok(())=> {
+                            self.is_blank=true;
+                            crate::notify_clients(state, StreamEvent::Blanked);
+                        },
                         Err(err) => println!("{:?}",&err)
                     }
             },
             Message::UnSetBlankScreen => {
                 let  state =self.streamer_state.as_ref().unwrap();
                     let arc_streamer_state =state.streamer_arc.lock();
-                    let streamer=arc_streamer_state.expect("errore  getting  arc").restart();
+                    let streamer=arc_streamer_state.expect("errore  getting  arc").show_live();
                     match streamer {
-                        true => {
-                            self.is_blank=false
+                        Ok(()) => {
+                            self.is_blank=false;
+                            crate::notify_clients(state, StreamEvent::Unblanked);
                         },
-                        false => {
-                            println!("Error in unblanking stream");
+                        Err(err) => {
+                            println!("Error in unblanking stream: {:?}", err);
                         },
                     }
             },
@@ -446,8 +1042,7 @@ impl ScreenSharer {
                     },
                     StreamingState::Play => {
                         let  state =self.streamer_state.as_ref().unwrap();
-                        let arc_streamer_state =state.streamer_arc.lock();
-                        let streamer=arc_streamer_state.expect("errore  getting  arc").pause();
+                        let streamer=crate::pause_streamer(state);
                         if streamer{
                             //we have paused the streamer
                             self.streaming_state = StreamingState::Pause;
@@ -469,9 +1064,7 @@ impl ScreenSharer {
                     },
                     StreamingState::Pause => {
                         let  state =self.streamer_state.as_ref().unwrap();
-                        let arc_streamer_state =state.streamer_arc.lock();
-                        let streamer=arc_streamer_state.expect("errore  getting  arc").un_pause();
-                        //.restart();
+                        let streamer=crate::resume_streamer(state);
                         if streamer{
                             //we have restarted the streamer
                             self.streaming_state = StreamingState::Play;
@@ -514,13 +1107,7 @@ impl ScreenSharer {
                                     if key ==  Key::Character("s".into()) && modifiers.control() =>
                                 {
 
-                                    cfg_if! {
-                                        if #[cfg(target_os = "linux")] {
-                                            Some(Message::RetIdPipewire) 
-                                        }
-                                        else{
-                                        Some(Message::StreamerPressed) 
-                                    }}
+                                    Some(Message::StartStreamPressed)
 
                                 },
                                 _ => None,
@@ -529,7 +1116,7 @@ impl ScreenSharer {
                         }
                         },
                         ApplicationState::Client => {
-                            Subscription::none()
+                            self.client_subscription()
                         },
                     }
                 },
@@ -539,17 +1126,26 @@ impl ScreenSharer {
                             Subscription::none()
                         },
                         ApplicationState::Streamer => {
-                            event::listen_with(|event, status, _queue| match (event, status) {
-                                (Event::Keyboard(KeyPressed { key, modifiers, .. }), Status::Ignored)
-                                    if key ==  Key::Character("p".into()) && modifiers.control() =>
-                                {
-                                    Some(Message::PauseStreaming) 
-                                },
-                                _ => None,
-                            })
+                            Subscription::batch([
+                                event::listen_with(|event, status, _queue| match (event, status) {
+                                    (Event::Keyboard(KeyPressed { key, modifiers, .. }), Status::Ignored)
+                                        if key ==  Key::Character("p".into()) && modifiers.control() =>
+                                    {
+                                        Some(Message::PauseStreaming) 
+                                    },
+                                    //anche dentro a un campo di testo: deve funzionare sempre
+                                    (Event::Keyboard(KeyPressed { key: Key::Named(Named::Escape), modifiers, .. }), _)
+                                        if modifiers.control() && modifiers.shift() =>
+                                    {
+                                        Some(Message::RevokeControl(None))
+                                    },
+                                    _ => None,
+                                }),
+                                self.session_subscription(),
+                            ])
                         },
                         ApplicationState::Client => {
-                            Subscription::none()
+                            self.client_subscription()
                         },
                     }
                     
@@ -560,17 +1156,26 @@ impl ScreenSharer {
                             Subscription::none()
                         },
                         ApplicationState::Streamer => {
-                            event::listen_with(|event, status, _queue| match (event, status) {
-                                (Event::Keyboard(KeyPressed { key, modifiers, .. }), Status::Ignored)
-                                    if key ==  Key::Character("r".into()) && modifiers.control() =>
-                                {
-                                    Some(Message::ResumeStreaming) 
-                                },
-                                _ => None,
-                            })
+                            Subscription::batch([
+                                event::listen_with(|event, status, _queue| match (event, status) {
+                                    (Event::Keyboard(KeyPressed { key, modifiers, .. }), Status::Ignored)
+                                        if key ==  Key::Character("r".into()) && modifiers.control() =>
+                                    {
+                                        Some(Message::ResumeStreaming) 
+                                    },
+                                    //anche dentro a un campo di testo: deve funzionare sempre
+                                    (Event::Keyboard(KeyPressed { key: Key::Named(Named::Escape), modifiers, .. }), _)
+                                        if modifiers.control() && modifiers.shift() =>
+                                    {
+                                        Some(Message::RevokeControl(None))
+                                    },
+                                    _ => None,
+                                }),
+                                self.session_subscription(),
+                            ])
                         },
                         ApplicationState::Client => {
-                            Subscription::none()
+                            self.client_subscription()
                         },
                     }
                     
@@ -579,6 +1184,40 @@ impl ScreenSharer {
         }
     }
 
+    /// Mentre si guarda uno stream si controlla periodicamente se il presentatore l'ha messo in pausa
+    fn client_subscription(&self) -> Subscription<Message> {
+        match self.connection_result {
+            ConnectionResult::Success => Subscription::batch([
+                iced::time::every(Duration::from_millis(500)).map(|_| Message::CheckStreamState),
+                iced::time::every(FRAME_POLL_INTERVAL).map(|_| Message::NextFrame),
+                //solo se il video non le ha già usate, mentre si controlla il desktop vanno al presentatore
+                event::listen_with(|event, status, _id| match (event, status) {
+                    (Event::Keyboard(KeyPressed { key: Key::Named(Named::F11), .. }), Status::Ignored) => {
+                        Some(Message::ToggleFullscreen)
+                    },
+                    (Event::Keyboard(KeyPressed { key: Key::Named(Named::Escape), .. }), Status::Ignored) => {
+                        Some(Message::ExitFullscreen)
+                    },
+                    (Event::Keyboard(KeyPressed { key: Key::Character(c), modifiers, .. }), Status::Ignored)
+                        if modifiers.command() =>
+                    {
+                        match c.as_str() {
+                            "0" => Some(Message::ChangeZoom(Zoom::Fit)),
+                            "1" => Some(Message::ChangeZoom(Zoom::Percent(100))),
+                            "=" | "+" => Some(Message::ZoomStep(1)),
+                            "-" => Some(Message::ZoomStep(-1)),
+                            _ => None,
+                        }
+                    },
+                    _ => None,
+                }),
+            ]),
+            _ if self.connection_waiting => iced::time::every(Duration::from_millis(200)).map(|_| Message::CheckConnection),
+            _ if self.session_browser.is_some() => iced::time::every(Duration::from_secs(1)).map(|_| Message::RefreshSessions),
+            _ => Subscription::none(),
+        }
+    }
+
     fn view(&self) -> Element<Message> {
        
        match self.application_state {
@@ -613,7 +1252,10 @@ impl ScreenSharer {
                         let main_text = text("Client")
                         .size(50);
                         
-                        let inner_text = text("waiting to connect to the stream...")
+                        let inner_text = match self.waiting_admission.load(Ordering::Relaxed) {
+                            true => text("waiting for the presenter to admit you..."),
+                            false => text("waiting to connect to the stream..."),
+                        }
                         .size(30);
 
                         let final_col = column![]
@@ -629,12 +1271,12 @@ impl ScreenSharer {
                         let main_text = text("Client")
                         .size(50);
         
-                        let back_icon = Icon::new(Handle::from_path("src/images/left.png"));
+                        let back_icon = Icon::new(Handle::from_bytes(BACK_ICON));
                         let back_area = MouseArea::new(back_icon)
                         .on_press(Message::ChangeApplicationState(ApplicationState::Start))
                         .interaction(mouse::Interaction::Pointer);
         
-                        let text_input_client = text_input("es.. 198.154.1.12", 
+                        let text_input_client = text_input("es.. 198.154.1.12 or 198.154.1.12:9000", 
                         &self.input_value_client)
                         .on_input(Message::InputChangedClient)
                         .padding(10)
@@ -650,14 +1292,14 @@ impl ScreenSharer {
                                 .padding(30)
                                 .style(button::success)
                                 .on_press(Message::Connection);
-                                client_icon = Icon::new(Handle::from_path("src/images/checked.png"));
+                                client_icon = Icon::new(Handle::from_bytes(CONNECTED_ICON));
                             },
                             false => {
                                 start_client_button = button("Connect to a screen sharing session")
                                 .width(500)
                                 .padding(30)
                                 .style(button::danger);
-                                client_icon = Icon::new(Handle::from_path("src/images/cross.png"));
+                                client_icon = Icon::new(Handle::from_bytes(FAILED_ICON));
                             },
                         }
         
@@ -671,6 +1313,36 @@ impl ScreenSharer {
                         .spacing(10)
                         .push(text_input_client)
                         .push(client_icon);
+
+                        let audio_checkbox = checkbox("Receive audio", self.receive_audio)
+                        .on_toggle(Message::ToggleReceiveAudio);
+
+                        let client_port_input = text_input("any", &self.client_port_input)
+                        .on_input(Message::ChangeClientPort)
+                        .padding(10)
+                        .width(100);
+
+                        let sessions_list = self.sessions_list();
+
+                        let client_pin_input = text_input("none", &self.client_pin_input)
+                        .on_input(Message::ChangeClientPin)
+                        .secure(true)
+                        .padding(10)
+                        .width(100);
+
+                        let nickname_input = text_input("Nickname", &self.nickname_input)
+                        .on_input(Message::ChangeNickname)
+                        .padding(10)
+                        .width(200);
+
+                        let options_row = row![
+                            nickname_input,
+                            audio_checkbox,
+                            text("Local port"), client_port_input,
+                            text("PIN"), client_pin_input,
+                        ]
+                        .spacing(10)
+                        .align_y(Alignment::Center);
         
                         
                         let content; 
@@ -680,62 +1352,36 @@ impl ScreenSharer {
                                 .spacing(15)
                                 .push(first_row)
                                 .push(second_row)
-                                .push(start_client_button);
+                                .push(options_row)
+                                .push(start_client_button)
+                                .push(sessions_list);
                             },
                             ConnectionResult::Success => {
-                                let primary_text = text("Client")
-                                .size(50);
-
-                                let success_text = text("Currently connected to the streaming at IP:")
-                                .size(26)
-                                .style(text::success);
-
-                                let ip_text = text(&self.input_value_client)
-                                .size(22);
-
-                                let recording_button;
-                                match self.is_recording {
-                                    true => {
-                                        recording_button = button("Stop recording")
-                                        .on_press(Message::StopRec)
-                                        .width(500)
-                                        .padding(30)
-                                        .style(button::danger);
-                                    },
-                                    false => {
-                                        recording_button = button("Start recording")
-                                        .on_press(Message::StartRecording)
-                                        .width(500)
-                                        .padding(30)
-                                        .style(button::success);
-                                    },
-                                };
-
-                                let finish_button = button("Stop watching the stream")
-                                .on_press(Message::StopClientPressed)
-                                .width(500)
-                                .padding(30);
-
-                                content = column![]
-                                .spacing(15)
-                                .push(primary_text)
-                                .push(success_text)
-                                .push(ip_text)
-                                .push(recording_button)
-                                .push(finish_button);
-                                
+                                //a schermo intero resta solo il video
+                                if self.fullscreen {
+                                    return self.video_pane();
+                                }
+                                return row![self.video_pane(), self.client_side_panel()].into();
                             },
-                            ConnectionResult::Failed => {
-                                let failed_text = text("Error in connecting to a streaming, please retry")
-                                .size(26)
+                            ConnectionResult::Failed(ref reason) | ConnectionResult::Closed(ref reason) => {
+                                let failed_text = match self.connection_result {
+                                    ConnectionResult::Closed(_) => text("The stream is over").style(text::secondary),
+                                    _ => text("Error in connecting to a streaming, please retry").style(text::danger),
+                                }
+                                .size(26);
+                                let reason_text = text(reason)
+                                .size(18)
                                 .style(text::danger);
 
                                 content = column![]
                                 .spacing(15)
                                 .push(first_row)
                                 .push(second_row)
+                                .push(options_row)
                                 .push(start_client_button)
-                                .push(failed_text);
+                                .push(failed_text)
+                                .push(reason_text)
+                                .push(sessions_list);
                             },
                         }
                         return center(content).into();},
@@ -751,7 +1397,7 @@ impl ScreenSharer {
                 //cambio il content in base al fatto che stiamo streammando o no
                 match self.streaming_state {
                     StreamingState::Starting => {
-                        let back_icon = Icon::new(Handle::from_path("src/images/left.png"));
+                        let back_icon = Icon::new(Handle::from_bytes(BACK_ICON));
                         let back_area = MouseArea::new(back_icon)
                         .on_press(Message::ChangeApplicationState(ApplicationState::Start))
                         .interaction(mouse::Interaction::Pointer);
@@ -793,14 +1439,23 @@ impl ScreenSharer {
 
                         cfg_if! {
                             if #[cfg(target_os = "linux")] {
-                                if self.can_start_stream{
+                                let missing_screen = self.x11_fallback
+                                    && self.capture_kind == CaptureKind::Screen
+                                    && self.selected_screen.is_none();
+                                if missing_screen {
+                                    start_button = button(button_text)
+                                    .padding(30)
+                                    .width(400)
+                                    .style(button::danger);
+                                }
+                                else if self.can_start_stream{
 
                                 
                         start_button = button(button_text)
                                 .padding(30)
                                 .width(400)
                                 .style(button::success)
-                                .on_press(Message::RetIdPipewire);
+                                .on_press(Message::StartStreamPressed);
                             }
                         
                         else{
@@ -813,14 +1468,20 @@ impl ScreenSharer {
                     }
                         
                         #[cfg(not(target_os = "linux"))]
-                        match self.selected_screen {
-                            Some(_) => { 
+                        let has_source = match self.capture_kind {
+                            CaptureKind::Screen => self.selected_screen.is_some(),
+                            CaptureKind::TestPattern => true,
+                            CaptureKind::MediaFile => self.media_file.is_some(),
+                        };
+                        #[cfg(not(target_os = "linux"))]
+                        match has_source {
+                            true => { 
                                 if self.can_start_stream==true{
                                 start_button = button(button_text)
                                 .padding(30)
                                 .width(400)
                                 .style(button::success)
-                                .on_press(Message::StreamerPressed);
+                                .on_press(Message::StartStreamPressed);
                                 }
                                 else{
                                     let button_text = text("Wait, closing previous stream");
@@ -832,21 +1493,130 @@ impl ScreenSharer {
                                     
                                 }
                             },
-                            None => {
+                            false => {
                                 start_button = button(button_text)
                                 .padding(30)
                                 .width(400)
                                 .style(button::danger);
                             },
                         }
+                        let preset_list = pick_list(SpeedPreset::ALL,
+                        Some(self.stream_config.preset),
+                        Message::ChangePreset)
+                        .width(195)
+                        .padding(10);
+
+                        let resolution_list = pick_list(OutputResolution::ALL,
+                        Some(self.stream_config.output_resolution),
+                        Message::ChangeResolution)
+                        .width(195)
+                        .padding(10);
+
+                        let framerate_list = pick_list(FRAMERATES,
+                        Some(self.stream_config.max_framerate),
+                        Message::ChangeFramerate)
+                        .padding(10);
+
+                        let bitrate_input = text_input("Bitrate (kbps)", &self.bitrate_input)
+                        .on_input(Message::ChangeBitrate)
+                        .padding(10)
+                        .width(100);
+
+                        let keyframe_input = text_input("Keyframe interval", &self.keyframe_input)
+                        .on_input(Message::ChangeKeyframeInterval)
+                        .padding(10)
+                        .width(70);
+
+                        let encoding_row = row![preset_list, resolution_list]
+                        .spacing(10);
+
+                        let discovery_port_input = text_input("Port", &self.discovery_port_input)
+                        .on_input(Message::ChangeDiscoveryPort)
+                        .padding(10)
+                        .width(100);
+
+                        let rtp_port_input = text_input("any", &self.rtp_port_input)
+                        .on_input(Message::ChangeRtpPort)
+                        .padding(10)
+                        .width(100);
+
+                        let session_row = row![
+                            text_input("Session title", &self.stream_config.session_name)
+                            .on_input(Message::ChangeSessionName)
+                            .padding(10)
+                            .width(195),
+                            text_input("Presenter name", &self.stream_config.presenter_name)
+                            .on_input(Message::ChangePresenterName)
+                            .padding(10)
+                            .width(195),
+                        ]
+                        .spacing(10);
+
+                        let pin_input = text_input("none", &self.pin_input)
+                        .on_input(Message::ChangePin)
+                        .secure(true)
+                        .padding(10)
+                        .width(100);
+
+                        let port_row = row![
+                            text("Session port"), discovery_port_input,
+                            text("RTP port"), rtp_port_input,
+                            text("PIN"), pin_input,
+                            checkbox("Waiting room", self.stream_config.waiting_room)
+                            .on_toggle(Message::ToggleWaitingRoom),
+                            checkbox("Chat", self.stream_config.chat_enabled)
+                            .on_toggle(Message::ToggleChat),
+                        ]
+                        .spacing(5)
+                        .align_y(Alignment::Center);
+
+                        let rate_row = row![
+                            bitrate_input, text("kbps"),
+                            framerate_list, text("fps"),
+                            keyframe_input, text("keyframe"),
+                        ]
+                        .spacing(5)
+                        .align_y(Alignment::Center);
+
+                        let source_list = pick_list(CaptureKind::ALL,
+                        Some(self.capture_kind),
+                        Message::ChangeCaptureKind)
+                        .width(400)
+                        .padding(10);
+
                         content = column![]
-                        .push(first_row);
+                        .push(first_row)
+                        .push(source_list);
                         #[cfg(not(target_os = "linux"))]
                         { 
                             content = content.push(screens_list);
                         }
+                        #[cfg(target_os = "linux")]
+                        if self.x11_fallback {
+                            //con ximagesrc il monitor si sceglie qui e non nella finestra del portale
+                            content = content.push(screens_list);
+                        }
   
+                        let audio_row = row![
+                            checkbox("Share system audio", self.stream_config.audio.system_audio)
+                            .on_toggle(Message::ToggleSystemAudio),
+                            checkbox("Share microphone", self.stream_config.audio.microphone)
+                            .on_toggle(Message::ToggleMicrophone),
+                            checkbox("Confidential (encrypted only)", self.stream_config.confidential)
+                            .on_toggle(Message::ToggleConfidential),
+                            checkbox("Show pointers to every viewer", self.stream_config.share_pointers)
+                            .on_toggle(Message::ToggleSharePointers),
+                            checkbox("Allow remote control", self.stream_config.remote_control != RemoteControlMode::Disabled)
+                            .on_toggle(Message::ToggleRemoteControl),
+                        ]
+                        .spacing(20);
+
                         content=content.push(selecting_area_button)
+                        .push(session_row)
+                        .push(encoding_row)
+                        .push(rate_row)
+                        .push(port_row)
+                        .push(audio_row)
                         .push(start_button);
                         if self.can_start_stream{
                             content=content.push(shortcut_text("Shortcut: Ctrl+s to start streaming"))
@@ -868,6 +1638,20 @@ impl ScreenSharer {
                                     .padding(30)
                         };
 
+                        let placeholder_list = pick_list(
+                            PlaceholderKind::ALL,
+                            Some(self.placeholder_kind),
+                            Message::ChangePlaceholderKind)
+                            .width(400);
+
+                        let change_area_button = match self.first_point {
+                            Some(_) => button("Reset the area to Full Screen"),
+                            None => button("Change the streamed area"),
+                        }
+                        .width(400)
+                        .padding(30)
+                        .on_press(Message::ToggleSelectingArea);
+
                         let pause_stream_button = button("Pause Stream")
                         .width(400)
                         .padding(30)
@@ -878,16 +1662,38 @@ impl ScreenSharer {
                         .padding(30)
                         .on_press(Message::StopStreamerPressed);
 
+                        let mut audio_row = row![].spacing(10);
+                        if self.stream_config.audio.system_audio {
+                            audio_row = audio_row.push(match self.system_audio_muted {
+                                false => button("Mute system audio").on_press(Message::MuteSystemAudio(true)),
+                                true => button("Unmute system audio").on_press(Message::MuteSystemAudio(false)),
+                            }.width(195).padding(15));
+                        }
+                        if self.stream_config.audio.microphone {
+                            audio_row = audio_row.push(match self.microphone_muted {
+                                false => button("Mute microphone").on_press(Message::MuteMicrophone(true)),
+                                true => button("Unmute microphone").on_press(Message::MuteMicrophone(false)),
+                            }.width(195).padding(15));
+                        }
+
                         content = column![]
                         .align_x(Alignment::Center)
                         .spacing(20)
                         .push(main_text)
+                        .push(self.remote_control_banner())
                         .push(play_text)
                         .push(ip_text)
                         .push(blankbutton)
+                        .push(placeholder_list)
+                        .push(change_area_button)
+                        .push(audio_row)
                         .push(pause_stream_button)
                         .push(end_stream_button)
-                        .push(shortcut_text("Shortcut: Ctrl+p to pause streaming"));
+                        .push(shortcut_text("Shortcut: Ctrl+p to pause streaming"))
+                        .push(self.join_requests_list())
+                        .push(self.viewers_list())
+                        .push(self.pointers_panel())
+                        .push(self.presenter_chat());
                     },
                     StreamingState::Pause => {
                         let pause_text = text("The streaming is currently in pause")
@@ -899,9 +1705,14 @@ impl ScreenSharer {
 
                         content = column![]
                         .push(main_text)
+                        .push(self.remote_control_banner())
                         .push(pause_text)
                         .push(end_stream_button)
-                        .push(shortcut_text("Shortcut: Ctrl+r to resume streaming"));
+                        .push(shortcut_text("Shortcut: Ctrl+r to resume streaming"))
+                        .push(self.join_requests_list())
+                        .push(self.viewers_list())
+                        .push(self.pointers_panel())
+                        .push(self.presenter_chat());
                         
                     },
                 }              
@@ -959,21 +1770,426 @@ fn style(&self, theme: &Theme) -> application::Appearance {
             Theme::default_style(theme)
         } 
 }
-    fn can_continue_client(&self) -> bool {  //valuta se l'ip inserito è valido "migliorabile controllando se è un ip raggiungibile"
-        self.input_value_client.clone().trim().parse::<IpAddr>().is_ok()
+    /// Se si sta già trasmettendo, aggiorna l'area catturata senza fermare lo streaming
+    fn apply_capture_region(&self) {
+        let Some(ref state) = self.streamer_state else {
+            return;
+        };
+        if self.first_point.is_some() && self.selected_screen.is_none() {
+            println!("Cannot crop the stream without knowing the screen size");
+            return;
+        }
+        let crop = dimension_to_crop(self.first_point, self.second_point, self.selected_screen);
+        let size = self.selected_screen.map(|screen| (
+            screen.width as i32 - crop.left - crop.right,
+            screen.height as i32 - crop.top - crop.bottom,
+        ));
+        let result = state.streamer_arc.lock().expect("errore getting arc").set_capture_region(crop);
+        match (result, size) {
+            (Ok(()), Some((width, height))) => crate::notify_clients(state, StreamEvent::ResolutionChanged { width, height }),
+            (Ok(()), None) => {},
+            (Err(e), _) => println!("Failed to change the streamed area: {}", e),
+        }
+    }
+
+    fn screen_source(&self, screen: Display) -> Result<Box<dyn CaptureSource>, ServerError> {
+        #[cfg(target_os = "linux")]
+        if self.x11_fallback {
+            let desktop_origin = self.available_display.iter()
+                .fold((screen.x, screen.y), |(x, y), display| (x.min(display.x), y.min(display.y)));
+            let source = capture::XImageSource::for_monitor(screen.x, screen.y, screen.width, screen.height, desktop_origin)?;
+            return Ok(Box::new(source));
+        }
+        #[cfg(target_os = "linux")]
+        return Ok(Box::new(capture::PipeWireSource {
+            node_id: screen.id,
+            origin: screen.position_known.then_some((screen.x, screen.y)),
+        }));
+        #[cfg(not(target_os = "linux"))]
+        Ok(capture::screen_source(screen.id as usize))
+    }
+
+    /// Quello che vedono i client quando lo schermo è oscurato
+    fn placeholder(&self) -> Placeholder {
+        match self.placeholder_kind {
+            PlaceholderKind::BlankImage => Placeholder::blank(),
+            PlaceholderKind::SolidColor => Placeholder::Color(0xff000000),
+            PlaceholderKind::RightBack => Placeholder::right_back(),
+            PlaceholderKind::CustomImage => self.placeholder_image.as_ref()
+                .and_then(|path| Placeholder::from_file(path)
+                    .map_err(|e| println!("Cannot read {}: {}", path.display(), e))
+                    .ok())
+                .unwrap_or_else(Placeholder::blank),
+        }
+    }
+
+    /// Clients in the waiting room, each one with the buttons to admit or deny it
+    fn join_requests_list(&self) -> Element<'_, Message> {
+        let mut list = column![].spacing(10).align_x(Alignment::Center);
+        if !self.join_requests.is_empty() {
+            list = list.push(text("Waiting to join").size(22));
+        }
+        for (client, nickname) in &self.join_requests {
+            list = list.push(row![
+                text(viewer_label(client, nickname.as_deref())).width(250),
+                button("Admit").on_press(Message::AnswerJoinRequest(*client, true)).style(button::success),
+                button("Deny").on_press(Message::AnswerJoinRequest(*client, false)).style(button::danger),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center));
+        }
+        list.into()
+    }
+
+    /// Viewer panel, pointers and chat refresh, and join requests sent by the discovery thread (only if the session has a waiting room)
+    fn session_subscription(&self) -> Subscription<Message> {
+        let admission = match self.streamer_state.as_ref().and_then(|state| state.admission_receiver.clone()) {
+            Some(receiver) => Subscription::run_with_id("admission", receiver.map(Message::AdmissionUpdate)),
+            None => Subscription::none(),
+        };
+        Subscription::batch([
+            admission,
+            iced::time::every(Duration::from_millis(500)).map(|_| Message::RefreshViewers),
+        ])
+    }
+
+    /// Viewers of the session with their health and the buttons to kick or ban them
+    fn viewers_list(&self) -> Element<'_, Message> {
+        let mut list = column![
+            text(format!("Viewers: {}", self.viewers.len())).size(22)
+        ]
+        .spacing(10)
+        .align_x(Alignment::Center);
+
+        for viewer in &self.viewers {
+            let name = viewer_label(&viewer.addr, viewer.nickname.as_deref());
+            let minutes = viewer.joined.elapsed().as_secs() / 60;
+            //senza heartbeat da più di due intervalli la connessione probabilmente ha problemi
+            let health = match viewer.last_seen.elapsed() > HEARTBEAT_INTERVAL * 2 {
+                false => text("connected").style(text::success),
+                true => text(format!("no heartbeat for {}s", viewer.last_seen.elapsed().as_secs())).style(text::danger),
+            };
+            let mut viewer_row = row![
+                text(name).width(250),
+                text(format!("joined {} min ago", minutes)).width(130),
+                health.width(170),
+                button("Kick").on_press(Message::RemoveViewer(viewer.addr, false)),
+                button("Ban").on_press(Message::RemoveViewer(viewer.addr, true)).style(button::danger),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center);
+            viewer_row = match viewer.control {
+                ControlStatus::None => viewer_row,
+                ControlStatus::Requested => viewer_row
+                    .push(button("Grant control").on_press(Message::GrantControl(viewer.addr)).style(button::success))
+                    .push(button("Deny").on_press(Message::RevokeControl(Some(viewer.addr)))),
+                ControlStatus::Granted => viewer_row
+                    .push(button("Revoke control").on_press(Message::RevokeControl(Some(viewer.addr))).style(button::danger)),
+            };
+            list = list.push(viewer_row);
+        }
+        list.into()
+    }
+
+    /// Red banner shown while a viewer controls the desktop, empty otherwise
+    fn remote_control_banner(&self) -> Element<'_, Message> {
+        let Some(controller) = self.viewers.iter().find(|viewer| viewer.control == ControlStatus::Granted) else {
+            return column![].into();
+        };
+        let name = viewer_label(&controller.addr, controller.nickname.as_deref());
+        container(row![
+            text(format!("Remote control active: {} is using your mouse and keyboard", name))
+            .size(20)
+            .color(Color::WHITE),
+            button("Revoke").on_press(Message::RevokeControl(None)),
+            shortcut_text("or Ctrl+Shift+Escape").color(Color::WHITE),
+        ]
+        .spacing(15)
+        .align_y(Alignment::Center))
+        .padding(15)
+        .style(|theme: &Theme| container::Style::default().background(theme.extended_palette().danger.base.color))
+        .into()
+    }
+
+    /// The received stream filling the window, with the recording and stop controls over it
+    fn video_pane(&self) -> Element<'_, Message> {
+        let video = Canvas::new(VideoView {
+            frame: self.video_frame.clone(),
+            frame_size: self.video_size,
+            zoom: self.zoom,
+            pan: self.pan,
+            controlling: self.in_control,
+        })
+        .width(Length::Fill)
+        .height(Length::Fill);
+
+        let recording_button = match self.is_recording {
+            true => button("Stop recording").on_press(Message::StopRec).style(button::danger),
+            false => button("Start recording").on_press(Message::StartRecording).style(button::success),
+        }
+        .padding(10);
+        let fullscreen_button = match self.fullscreen {
+            true => button("Exit fullscreen").on_press(Message::ExitFullscreen),
+            false => button("Fullscreen").on_press(Message::ToggleFullscreen),
+        }
+        .padding(10);
+        let mut controls = row![
+            recording_button,
+            button("Stop watching").on_press(Message::StopClientPressed).padding(10),
+            pick_list(Zoom::ALL, Some(self.zoom), Message::ChangeZoom).padding(10),
+            fullscreen_button,
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center);
+        if self.receive_audio {
+            controls = controls.push(match self.client_muted {
+                false => button("Mute audio").on_press(Message::MuteClientAudio(true)),
+                true => button("Unmute audio").on_press(Message::MuteClientAudio(false)),
+            }
+            .padding(10));
+        }
+        if self.stream_status.paused {
+            controls = controls.push(text("The presenter paused the stream").color(Color::WHITE));
+        }
+        if self.stream_status.blanked {
+            controls = controls.push(text("The presenter blanked the screen").color(Color::WHITE));
+        }
+
+        let hints = match (self.zoom, self.fullscreen) {
+            (Zoom::Fit, _) => "F11 fullscreen, Esc exit, Ctrl+1 1:1, Ctrl+= / Ctrl+- zoom",
+            (_, false) => "Drag to pan, Ctrl+wheel zoom, Ctrl+0 fit, F11 fullscreen",
+            (_, true) => "Drag to pan, Ctrl+wheel zoom, Ctrl+0 fit, Esc exit fullscreen",
+        };
+        let controls = column![controls, shortcut_text(hints).color(Color::WHITE)]
+        .spacing(5)
+        .align_x(Alignment::Center);
+
+        //i comandi stanno sopra al video, in basso al centro
+        let overlay = container(
+            container(controls)
+            .padding(10)
+            .style(|_theme: &Theme| container::Style::default().background(Color::from_rgba(0.0, 0.0, 0.0, 0.6))),
+        )
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .padding(20)
+        .align_x(Alignment::Center)
+        .align_y(Alignment::End);
+
+        widget::Stack::new()
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .push(Element::from(video).map(Message::Video))
+        .push(overlay)
+        .into()
+    }
+
+    /// Next to the video: the session, the pointer, the remote control and the chat
+    fn client_side_panel(&self) -> Element<'_, Message> {
+        let title_text = match self.watching {
+            Some(ref session) => text(format!("{} by {}", session.session_name, session.presenter_name)),
+            None => text(&self.input_value_client),
+        }
+        .size(22);
+
+        let mut panel = column![
+            title_text,
+            text(format!("Connected to {}", self.input_value_client)).style(text::success),
+        ]
+        .spacing(15)
+        .padding(15)
+        .width(530);
+
+        if let Some((width, height)) = self.stream_status.resolution {
+            panel = panel.push(text(format!("Shared area: {}x{}", width, height)));
+        }
+
+        panel
+        .push(checkbox("Point on the presenter's screen (click on the video)", self.pointing)
+            .on_toggle(Message::TogglePointing))
+        .push(self.client_control_panel())
+        .push(self.chat_pane(self.stream_status.chat_enabled))
+        .into()
+    }
+
+    /// Buttons of the viewer to ask for the control of the presenter's desktop and to give it back
+    fn client_control_panel(&self) -> Element<'_, Message> {
+        if !self.watching.as_ref().is_some_and(|session| session.remote_control) {
+            return column![].into();
+        }
+        if self.in_control {
+            return container(row![
+                text("You are controlling the presenter's desktop: keep the mouse over the video to type")
+                .size(20)
+                .color(Color::WHITE),
+                button("Release control").on_press(Message::ReleaseControl),
+            ]
+            .spacing(15)
+            .align_y(Alignment::Center))
+            .padding(15)
+            .style(|theme: &Theme| container::Style::default().background(theme.extended_palette().danger.base.color))
+            .into();
+        }
+        match self.control_requested {
+            true => row![
+                text("Waiting for the presenter to give you the control").style(text::secondary),
+                button("Withdraw").on_press(Message::ReleaseControl),
+            ]
+            .spacing(15)
+            .align_y(Alignment::Center)
+            .into(),
+            false => button("Request remote control")
+                .on_press(Message::RequestControl)
+                .padding(10)
+                .into(),
+        }
+    }
+
+    /// Where the viewers are pointing, on a miniature of the shared area and in screen coordinates
+    fn pointers_panel(&self) -> Element<'_, Message> {
+        let mut panel = column![
+            checkbox("Show pointers to every viewer", self.stream_config.share_pointers)
+            .on_toggle(Message::ToggleSharePointers),
+        ]
+        .spacing(10)
+        .align_x(Alignment::Center);
+        if self.pointers.is_empty() {
+            return panel.into();
+        }
+
+        let marks = self.pointers.iter()
+            .map(|(mark, _)| {
+                let (red, green, blue) = mark.color();
+                (mark.x as f32, mark.y as f32, Color::from_rgb8(red, green, blue), mark.name.clone())
+            })
+            .collect();
+        //la miniatura ha le proporzioni dello stream
+        let height = match self.stream_size {
+            Some((width, height)) if width > 0 => 320.0 * height as f32 / width as f32,
+            _ => 180.0,
+        };
+        panel = panel.push(Canvas::new(PointerCanvas { marks }).width(320).height(height));
+
+        for (mark, on_screen) in &self.pointers {
+            let (red, green, blue) = mark.color();
+            let label = match on_screen {
+                Some((x, y)) => format!("{} is pointing at {}, {}", mark.name, x, y),
+                None => format!("{} is pointing", mark.name),
+            };
+            panel = panel.push(text(label).color(Color::from_rgb8(red, green, blue)));
+        }
+        panel.into()
+    }
+
+    /// Chat of the streamer screen, with the switch that lets the viewers write or not
+    fn presenter_chat(&self) -> Element<'_, Message> {
+        column![
+            checkbox("Viewers can chat", self.stream_config.chat_enabled)
+            .on_toggle(Message::ToggleChat),
+            self.chat_pane(true),
+        ]
+        .spacing(10)
+        .into()
+    }
+
+    /// History of the chat and the box to write in it, without `can_write` only the history is shown
+    fn chat_pane(&self, can_write: bool) -> Element<'_, Message> {
+        let mut history = column![].spacing(5);
+        for message in &self.chat_messages {
+            let line = text(format!("{}: {}", message.author, message.text));
+            //i messaggi del presentatore si distinguono da quelli degli spettatori
+            history = history.push(match message.from_presenter {
+                true => line.style(text::primary),
+                false => line,
+            });
+        }
+        if self.chat_messages.is_empty() {
+            history = history.push(text("No messages yet").style(text::secondary));
+        }
+
+        let mut pane = column![
+            text("Chat").size(22),
+            scrollable(history).height(150).width(500).anchor_bottom(),
+        ]
+        .spacing(10);
+
+        pane = match can_write {
+            true => pane.push(row![
+                text_input("Write a message", &self.chat_input)
+                .on_input(Message::ChangeChatInput)
+                .on_submit(Message::SendChat)
+                .padding(10)
+                .width(400),
+                button("Send").on_press(Message::SendChat),
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center)),
+            false => pane.push(text("The presenter disabled the chat").style(text::secondary)),
+        };
+        pane.into()
+    }
+
+    /// Sessions announced on the LAN, clicking one connects to it
+    fn sessions_list(&self) -> Element<'_, Message> {
+        if self.discovered_sessions.is_empty() {
+            return text("Looking for sessions on the local network...")
+            .size(18)
+            .style(text::secondary)
+            .into();
+        }
+
+        let mut list = column![text("Sessions on the local network").size(22)].spacing(10);
+        for session in &self.discovered_sessions {
+            let viewers = match session.viewers {
+                1 => "1 viewer".to_string(),
+                n => format!("{} viewers", n),
+            };
+            let pin = if session.pin_required { ", PIN required" } else { "" };
+            let label = format!("{} - {} ({}, {}{})", session.session_name, session.presenter_name, viewers, session.address, pin);
+            list = list.push(button(text(label))
+                .width(500)
+                .padding(15)
+                .on_press(Message::JoinSession(session.address)));
+        }
+        list.into()
+    }
+
+    fn can_continue_client(&self) -> bool {  //valuta se l'ip (o ip:porta) inserito è valido "migliorabile controllando se è un ip raggiungibile"
+        discovery::parse_server_address(&self.input_value_client).is_some()
 
     }
 
 
+}
+/// Nickname of a viewer with its address, or only the address if it has no nickname
+fn viewer_label(addr: &SocketAddr, nickname: Option<&str>) -> String {
+    match nickname {
+        Some(nickname) => format!("{} ({})", nickname, addr),
+        None => addr.to_string(),
+    }
 }
 fn shortcut_text<>(label: &str) ->  iced::widget::Text {
     text(label).size(16).color(Color::from_rgb(0.5, 0.5, 0.5))
 }
+#[cfg(target_os = "linux")]
+const PORTAL_UNAVAILABLE: u32 = 3;
+
+#[cfg(target_os = "linux")]
+async fn portal_available() -> bool {
+    Screencast::new().await.is_ok()
+}
+
 #[cfg(target_os = "linux")]
 async fn pipewirerec() -> Result<Display,u32>{
-    let proxy = Screencast::new().await.expect("couln not start screencast proxi");
+    let proxy = match Screencast::new().await {
+        Ok(proxy) => proxy,
+        Err(e) => { println!("screencast portal not available: {:?}", e); return Err(PORTAL_UNAVAILABLE); }
+    };
 
-    let session = proxy.create_session().await.expect("couln not start screencast session");
+    let session = match proxy.create_session().await {
+        Ok(session) => session,
+        Err(e) => { println!("could not start screencast session: {:?}", e); return Err(PORTAL_UNAVAILABLE); }
+    };
     proxy
         .select_sources(
             &session,
@@ -1000,14 +2216,19 @@ async fn pipewirerec() -> Result<Display,u32>{
 
 
         let (width, height) = stream.size().unwrap_or((0, 0));
+        //solo per i monitor, serve al controllo remoto
+        let position = stream.position();
 
     let frequency = 60.0; // default for freq 
 
     let display = Display {
         id: stream.pipe_wire_node_id(),
+        x: position.map_or(0, |(x, _)| x),
+        y: position.map_or(0, |(_, y)| y),
         width: width as u32,
         height: height as u32,
         frequency,
+        position_known: position.is_some(),
     };
 
     println!("Generated Display: {:?}", display);
@@ -2,4 +2,6 @@ pub mod gui_main;
 pub mod selector_draw;
 pub mod display;
 pub mod icon;
-pub mod cropper;
\ No newline at end of file
+pub mod cropper;
+pub mod pointer_draw;
+pub mod video_view;
\ No newline at end of file
@@ -0,0 +1,44 @@
+use iced::{mouse, widget::canvas::{Frame, Geometry, Path, Program, Text}, Color, Point, Rectangle, Renderer, Size, Theme};
+
+
+/// Miniature of the shared area with the marks of the viewers, positions from 0 to 1
+pub struct PointerCanvas {
+    pub marks: Vec<(f32, f32, Color, String)>,
+}
+
+impl<Message> Program<Message> for PointerCanvas {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+
+        frame.fill_rectangle(Point::ORIGIN, bounds.size(), Color::from_rgba(0.0, 0.0, 0.0, 0.6));
+
+        for (x, y, color, name) in &self.marks {
+            let center = Point::new(x * bounds.width, y * bounds.height);
+            frame.fill(&Path::circle(center, 6.0), *color);
+            frame.fill_text(Text {
+                content: name.clone(),
+                position: Point::new(center.x + 9.0, center.y - 8.0),
+                color: *color,
+                size: 14.0.into(),
+                ..Text::default()
+            });
+        }
+
+        // bordo dell'area condivisa
+        frame.stroke(
+            &Path::rectangle(Point::ORIGIN, Size::new(bounds.width, bounds.height)),
+            iced::widget::canvas::Stroke::default().with_color(Color::from_rgb(0.5, 0.5, 0.5)),
+        );
+
+        vec![frame.into_geometry()]
+    }
+}
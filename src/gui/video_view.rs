@@ -0,0 +1,363 @@
+use std::fmt;
+use iced::{keyboard::{self, key::Named, Key, Location, Modifiers}, mouse, widget::canvas::{event, Event, Frame, Geometry, Program}, widget::image::Handle, Color, Point, Rectangle, Renderer, Size, Theme, Vector};
+use crate::connection::protocol::InputEvent;
+
+
+//pixel di scorrimento del touchpad che valgono uno scatto della rotella
+const PIXELS_PER_SCROLL_STEP: f32 = 50.0;
+//pixel spostati da uno scatto della rotella quando il video è ingrandito
+const PAN_PER_SCROLL_STEP: f32 = 60.0;
+//sotto questo movimento il trascinamento è un clic
+const DRAG_THRESHOLD: f32 = 4.0;
+
+
+/// Size of the received video in the window, only for this viewer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zoom {
+    Fit,
+    Percent(u16), // 100 = un pixel dello stream per pixel dello schermo
+}
+
+impl Zoom {
+    pub const ALL: [Zoom; 6] = [
+        Zoom::Fit,
+        Zoom::Percent(100),
+        Zoom::Percent(150),
+        Zoom::Percent(200),
+        Zoom::Percent(300),
+        Zoom::Percent(400),
+    ];
+
+    /// The next level of `ALL`, towards the bigger ones with `steps > 0`
+    pub fn step(self, steps: i32) -> Zoom {
+        let index = Self::ALL.iter().position(|zoom| *zoom == self).unwrap_or(0) as i32;
+        Self::ALL[(index + steps).clamp(0, Self::ALL.len() as i32 - 1) as usize]
+    }
+
+    fn scale(self, frame: Size, widget: Size) -> f32 {
+        match self {
+            Zoom::Fit => (widget.width / frame.width).min(widget.height / frame.height),
+            Zoom::Percent(percent) => percent as f32 / 100.0,
+        }
+    }
+}
+
+impl fmt::Display for Zoom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Zoom::Fit => write!(f, "Zoom: fit"),
+            Zoom::Percent(100) => write!(f, "Zoom: 1:1"),
+            Zoom::Percent(percent) => write!(f, "Zoom: {}%", percent),
+        }
+    }
+}
+
+
+/// What the viewer did on the video
+#[derive(Debug, Clone, PartialEq)]
+pub enum VideoAction {
+    /// Mouse and keyboard for the presenter's desktop, only while `controlling`
+    Input(InputEvent),
+    /// Click without dragging, position from 0 to 1 across the frame
+    Clicked(f64, f64),
+    /// New offset of the video from the center of the widget, already limited to the video
+    Pan(Vector),
+    Zoom(Zoom),
+}
+
+
+/// The received stream, fitted in the widget with black borders or zoomed and panned.
+/// What the viewer does on it becomes a `VideoAction` with the position relative to the frame
+pub struct VideoView {
+    pub frame: Option<Handle>,
+    pub frame_size: (u32, u32),
+    pub zoom: Zoom,
+    pub pan: Vector,
+    pub controlling: bool, // mouse e tastiera sopra al video vanno al presentatore
+}
+
+/// Interaction in progress on the video
+#[derive(Default)]
+pub struct ViewState {
+    scroll_x: f32, // scorrimento del touchpad non ancora mandato, meno di uno scatto
+    scroll_y: f32,
+    drag: Option<(Point, Vector, bool)>, // dove è iniziato, pan di partenza e se si è mosso abbastanza
+    modifiers: Modifiers,
+}
+
+impl VideoView {
+    fn frame_size(&self) -> Option<Size> {
+        let (width, height) = self.frame_size;
+        (width > 0 && height > 0).then(|| Size::new(width as f32, height as f32))
+    }
+
+    /// Part of the widget covered by the video, it can be bigger than the widget when zoomed
+    fn video_area(&self, size: Size) -> Rectangle {
+        let Some(frame) = self.frame_size() else {
+            return Rectangle::new(Point::ORIGIN, size);
+        };
+        let scale = self.zoom.scale(frame, size);
+        let video_size = Size::new(frame.width * scale, frame.height * scale);
+        let pan = self.clamp_pan(self.pan, video_size, size);
+        let origin = Point::new(
+            (size.width - video_size.width) / 2.0 + pan.x,
+            (size.height - video_size.height) / 2.0 + pan.y,
+        );
+        Rectangle::new(origin, video_size)
+    }
+
+    /// Offset that keeps the widget covered by the video, a smaller video stays centered
+    fn clamp_pan(&self, pan: Vector, video: Size, widget: Size) -> Vector {
+        let max_x = ((video.width - widget.width) / 2.0).max(0.0);
+        let max_y = ((video.height - widget.height) / 2.0).max(0.0);
+        Vector::new(pan.x.clamp(-max_x, max_x), pan.y.clamp(-max_y, max_y))
+    }
+
+    fn pan_by(&self, start: Vector, delta: Vector, widget: Size) -> Vector {
+        let video = self.video_area(widget).size();
+        self.clamp_pan(start + delta, video, widget)
+    }
+
+    /// True if the video does not fit in the widget and can be dragged around
+    fn can_pan(&self, widget: Size) -> bool {
+        let video = self.video_area(widget);
+        video.width > widget.width + 0.5 || video.height > widget.height + 0.5
+    }
+
+    /// Position of the cursor across the frame, from 0 to 1, `None` outside of the video
+    fn position(&self, bounds: Rectangle, cursor: mouse::Cursor) -> Option<(f64, f64)> {
+        let area = self.video_area(bounds.size());
+        cursor.position_in(bounds)
+            .filter(|cursor| area.contains(*cursor))
+            .and_then(|_| self.clamped_position(bounds, cursor))
+    }
+
+    /// Like `position` but outside of the video the nearest point of the frame
+    fn clamped_position(&self, bounds: Rectangle, cursor: mouse::Cursor) -> Option<(f64, f64)> {
+        let cursor = cursor.position()?;
+        let area = self.video_area(bounds.size());
+        let x = (cursor.x - bounds.x - area.x) / area.width;
+        let y = (cursor.y - bounds.y - area.y) / area.height;
+        Some((x.clamp(0.0, 1.0) as f64, y.clamp(0.0, 1.0) as f64))
+    }
+
+    /// Whole wheel steps, positive down and right like in the protocol
+    fn scroll_steps(state: &mut ViewState, delta: mouse::ScrollDelta) -> (f32, f32) {
+        //per iced un delta positivo è la rotella in su
+        match delta {
+            mouse::ScrollDelta::Lines { x, y } => (-x, -y),
+            mouse::ScrollDelta::Pixels { x, y } => {
+                state.scroll_x -= x / PIXELS_PER_SCROLL_STEP;
+                state.scroll_y -= y / PIXELS_PER_SCROLL_STEP;
+                let steps = (state.scroll_x.trunc(), state.scroll_y.trunc());
+                state.scroll_x -= steps.0;
+                state.scroll_y -= steps.1;
+                steps
+            },
+        }
+    }
+
+    /// Mouse and keyboard for the presenter, the view does not move
+    fn remote_input(&self, state: &mut ViewState, event: &Event, bounds: Rectangle, cursor: mouse::Cursor) -> Option<InputEvent> {
+        //un pulsante rilasciato fuori dal video non deve restare premuto dal presentatore
+        let position = match event {
+            Event::Mouse(mouse::Event::ButtonReleased(_)) => self.clamped_position(bounds, cursor),
+            _ => self.position(bounds, cursor),
+        };
+        let (x, y) = position?;
+        match *event {
+            Event::Mouse(mouse::Event::CursorMoved { .. }) => Some(InputEvent::MouseMove { x, y }),
+            Event::Mouse(mouse::Event::ButtonPressed(button)) => Some(InputEvent::MouseButton { x, y, button: x11_button(button)?, pressed: true }),
+            Event::Mouse(mouse::Event::ButtonReleased(button)) => Some(InputEvent::MouseButton { x, y, button: x11_button(button)?, pressed: false }),
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
+                let (dx, dy) = Self::scroll_steps(state, delta);
+                (dx != 0.0 || dy != 0.0).then_some(InputEvent::Scroll { x, y, dx: dx as f64, dy: dy as f64 })
+            },
+            Event::Keyboard(keyboard::Event::KeyPressed { ref key, location, .. }) => {
+                Some(InputEvent::Key { key: keysym_name(key, location)?, pressed: true })
+            },
+            Event::Keyboard(keyboard::Event::KeyReleased { ref key, location, .. }) => {
+                Some(InputEvent::Key { key: keysym_name(key, location)?, pressed: false })
+            },
+            _ => None,
+        }
+    }
+
+    /// Dragging pans, Ctrl+wheel zooms, the wheel pans vertically and a click points
+    fn view_action(&self, state: &mut ViewState, event: &Event, bounds: Rectangle, cursor: mouse::Cursor) -> Option<VideoAction> {
+        match *event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let start = cursor.position_over(bounds)?;
+                state.drag = Some((start, self.pan, false));
+                None
+            },
+            Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                let (start, start_pan, moved) = state.drag.as_mut()?;
+                let delta = position - *start;
+                *moved = *moved || delta.x.abs() > DRAG_THRESHOLD || delta.y.abs() > DRAG_THRESHOLD;
+                if !*moved || !self.can_pan(bounds.size()) {
+                    return None;
+                }
+                Some(VideoAction::Pan(self.pan_by(*start_pan, delta, bounds.size())))
+            },
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                let (_, _, moved) = state.drag.take()?;
+                if moved {
+                    return None;
+                }
+                let (x, y) = self.position(bounds, cursor)?;
+                Some(VideoAction::Clicked(x, y))
+            },
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
+                if !cursor.is_over(bounds) {
+                    return None;
+                }
+                let (dx, dy) = Self::scroll_steps(state, delta);
+                if state.modifiers.control() {
+                    return (dy != 0.0).then(|| VideoAction::Zoom(self.zoom.step(-dy as i32)));
+                }
+                if (dx == 0.0 && dy == 0.0) || !self.can_pan(bounds.size()) {
+                    return None;
+                }
+                let delta = Vector::new(-dx * PAN_PER_SCROLL_STEP, -dy * PAN_PER_SCROLL_STEP);
+                Some(VideoAction::Pan(self.pan_by(self.pan, delta, bounds.size())))
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Program<VideoAction> for VideoView {
+    type State = ViewState;
+
+    fn update(
+        &self,
+        state: &mut Self::State,
+        event: Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> (event::Status, Option<VideoAction>) {
+        if let Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) = event {
+            state.modifiers = modifiers;
+        }
+        let action = match self.controlling {
+            true => self.remote_input(state, &event, bounds, cursor).map(VideoAction::Input),
+            false => self.view_action(state, &event, bounds, cursor),
+        };
+        match action {
+            Some(action) => (event::Status::Captured, Some(action)),
+            None => (event::Status::Ignored, None),
+        }
+    }
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        frame.fill_rectangle(Point::ORIGIN, bounds.size(), Color::BLACK);
+        if let Some(ref handle) = self.frame {
+            //quello che esce dal widget viene tagliato dal canvas
+            frame.draw_image(self.video_area(bounds.size()), handle);
+        }
+        vec![frame.into_geometry()]
+    }
+
+    fn mouse_interaction(
+        &self,
+        state: &Self::State,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> mouse::Interaction {
+        if self.position(bounds, cursor).is_none() {
+            return mouse::Interaction::default();
+        }
+        match state.drag {
+            _ if self.controlling => mouse::Interaction::Crosshair,
+            Some((_, _, true)) => mouse::Interaction::Grabbing,
+            _ if self.can_pan(bounds.size()) => mouse::Interaction::Grab,
+            _ => mouse::Interaction::Crosshair,
+        }
+    }
+}
+
+
+fn x11_button(button: mouse::Button) -> Option<u8> {
+    match button {
+        mouse::Button::Left => Some(1),
+        mouse::Button::Middle => Some(2),
+        mouse::Button::Right => Some(3),
+        _ => None,
+    }
+}
+
+
+/// X keysym name of a key, as `KeyCode::from_name` of the presenter expects it.
+/// `key` is the one without modifiers: Shift+1 is Shift plus "1"
+fn keysym_name(key: &Key, location: Location) -> Option<String> {
+    let right = location == Location::Right;
+    let name = match key {
+        Key::Character(c) => {
+            let mut chars = c.chars();
+            let (Some(c), None) = (chars.next(), chars.next()) else {
+                return None;
+            };
+            return match c {
+                c if c.is_ascii_alphanumeric() => Some(c.to_ascii_lowercase().to_string()),
+                '-' => Some("minus".to_string()),
+                '=' => Some("equal".to_string()),
+                '[' => Some("bracketleft".to_string()),
+                ']' => Some("bracketright".to_string()),
+                ';' => Some("semicolon".to_string()),
+                '\'' => Some("apostrophe".to_string()),
+                '`' => Some("grave".to_string()),
+                '\\' => Some("backslash".to_string()),
+                ',' => Some("comma".to_string()),
+                '.' => Some("period".to_string()),
+                '/' => Some("slash".to_string()),
+                _ => None,
+            };
+        },
+        Key::Named(Named::Enter) => "Return",
+        Key::Named(Named::Backspace) => "BackSpace",
+        Key::Named(Named::Tab) => "Tab",
+        Key::Named(Named::Escape) => "Escape",
+        Key::Named(Named::Space) => "space",
+        Key::Named(Named::Delete) => "Delete",
+        Key::Named(Named::Insert) => "Insert",
+        Key::Named(Named::Home) => "Home",
+        Key::Named(Named::End) => "End",
+        Key::Named(Named::PageUp) => "Page_Up",
+        Key::Named(Named::PageDown) => "Page_Down",
+        Key::Named(Named::ArrowLeft) => "Left",
+        Key::Named(Named::ArrowUp) => "Up",
+        Key::Named(Named::ArrowRight) => "Right",
+        Key::Named(Named::ArrowDown) => "Down",
+        Key::Named(Named::Shift) if right => "Shift_R",
+        Key::Named(Named::Shift) => "Shift_L",
+        Key::Named(Named::Control) if right => "Control_R",
+        Key::Named(Named::Control) => "Control_L",
+        Key::Named(Named::Alt) if right => "Alt_R",
+        Key::Named(Named::Alt) => "Alt_L",
+        Key::Named(Named::Super) => "Super_L",
+        Key::Named(Named::CapsLock) => "Caps_Lock",
+        Key::Named(Named::F1) => "F1",
+        Key::Named(Named::F2) => "F2",
+        Key::Named(Named::F3) => "F3",
+        Key::Named(Named::F4) => "F4",
+        Key::Named(Named::F5) => "F5",
+        Key::Named(Named::F6) => "F6",
+        Key::Named(Named::F7) => "F7",
+        Key::Named(Named::F8) => "F8",
+        Key::Named(Named::F9) => "F9",
+        Key::Named(Named::F10) => "F10",
+        Key::Named(Named::F11) => "F11",
+        Key::Named(Named::F12) => "F12",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
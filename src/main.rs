@@ -1,8 +1,13 @@
 use std::error::Error;
-use std::net::IpAddr;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex, mpsc};
+use std::sync::atomic::AtomicBool;
 use std::thread;
+use std::time::Duration;
 use streamer::streamer::DimensionToCrop;
+use streamer::config::{RemoteControlMode, StreamConfig};
+use streamer::capture::{self, CaptureSource, FileSource, TestSource};
+use streamer::pointer::POINTER_DURATION;
 
 mod streamer;
 mod connection;
@@ -12,10 +17,12 @@ mod gui;
 use crate::gui::gui_main::run_iced;
 
 
-use streamer::streamer::ScreenStreamer;
+use streamer::streamer::{ScreenStreamer, VIDEO_CODEC};
 use streamer::client::StreamerClient;
 use connection::client::DiscoveryClient;
 use connection::server::DiscoveryServer;
+use connection::registry::{AdmissionEvent, ClientEvent, ViewerInfo};
+use connection::protocol::{ChatMessage, SessionInfo, StreamEvent, ANNOUNCE_PORT, PROTOCOL_VERSION};
 
 
 #[cfg(target_os = "macos")]
@@ -24,9 +31,20 @@ extern "C" {
     fn CFRunLoopRun();
 }
 
+/// How often the headless mode checks whether the source of a session ended
+const SOURCE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
 #[derive(PartialEq, Eq, Debug)] 
 enum ControlMessage {
     Stop,
+    Notify(StreamEvent),
+    Admit(SocketAddr),
+    Deny(SocketAddr),
+    Kick(SocketAddr),
+    Ban(SocketAddr),
+    Chat(String),
+    GrantControl(SocketAddr),
+    RevokeControl(Option<SocketAddr>), // None = chi ha il controllo in questo momento
 }
 
 struct StreamerState {
@@ -34,36 +52,116 @@ struct StreamerState {
     client_thread: thread::JoinHandle<()>,
     discovery_thread: thread::JoinHandle<()>,
     streamer_arc: Arc<Mutex<ScreenStreamer>>,
+    admission_receiver: Option<async_std::channel::Receiver<AdmissionEvent>>, // solo con la sala d'attesa
+    viewers: Arc<Mutex<Vec<ViewerInfo>>>,
+    chat: Arc<Mutex<Vec<ChatMessage>>>,
+    #[cfg(target_os = "linux")]
+    panic_hotkey: Option<streamer::hotkey::PanicHotkey>,
 }
 
 
-fn start_streamer(dimension: DimensionToCrop, num_monitor: usize) -> Result<StreamerState, Box<dyn Error>> {
+fn start_streamer(dimension: DimensionToCrop, source: Box<dyn CaptureSource>, config: StreamConfig) -> Result<StreamerState, Box<dyn Error>> {
 
 
     let (control_sender, control_receiver) = mpsc::channel();
     let (client_sender, client_receiver) = mpsc::channel();
 
-    let streamer = ScreenStreamer::new(dimension, num_monitor).expect("errore creazione scren streamer");
+    println!("Capturing from {}", source.name());
+    let mut streamer = ScreenStreamer::new(dimension, source, config.clone())?;
+
+    //il tasto di emergenza funziona anche quando la finestra non ha il focus, senza non si inietta nulla
+    #[cfg(target_os = "linux")]
+    let panic_hotkey = match streamer.remote_control_available() {
+        true => {
+            let panic_sender = control_sender.clone();
+            let hotkey = streamer::hotkey::PanicHotkey::watch(move || {
+                let _ = panic_sender.send(ControlMessage::RevokeControl(None));
+            });
+            match hotkey {
+                Ok(hotkey) => Some(hotkey),
+                //la prova a vuoto non tocca il desktop, non serve poterla interrompere
+                Err(_) if config.remote_control == RemoteControlMode::DryRun => None,
+                Err(e) => {
+                    println!("Remote control disabled, the panic hotkey is not available: {}", e);
+                    streamer.disable_remote_control();
+                    None
+                },
+            }
+        },
+        false => None,
+    };
+
+    let srtp_key = streamer.srtp_key();
+    let session = SessionInfo {
+        version: PROTOCOL_VERSION,
+        session_name: config.session_name.clone(),
+        presenter_name: config.presenter_name.clone(),
+        codec: VIDEO_CODEC.to_string(),
+        video_caps: streamer.video_caps(),
+        audio_caps: streamer.audio_caps(),
+        client_ip: String::new(),
+        stream_port: 0,
+        leave_token: 0,
+        srtp_key: None, // cifrata per ogni client dal DiscoveryServer
+        chat_enabled: config.chat_enabled,
+        remote_control: streamer.remote_control_available(),
+    };
     let streamer_arc = Arc::new(Mutex::new(streamer));
 
-    let mut discovery_server = DiscoveryServer::new(client_sender);
+    let (admission_sender, admission_receiver) = match config.waiting_room {
+        true => {
+            let (sender, receiver) = async_std::channel::unbounded();
+            (Some(sender), Some(receiver))
+        },
+        false => (None, None),
+    };
+
+    let mut discovery_server = DiscoveryServer::new(client_sender, session, config.discovery_port, config.pin.clone(), srtp_key, admission_sender)?;
+    let viewers = discovery_server.viewers();
+    let chat = discovery_server.chat();
     let discovery_thread = thread::spawn(move || {
         println!("Starting discovery server...");
         discovery_server.run_discovery_listener(control_receiver).expect("Failed to run discovery server");
-        println!("finisce mai il server ????...");
     });    
 
     let streamer_arc_clone = Arc::clone(&streamer_arc);
     let client_thread = thread::spawn(move || {
-        while let Ok(client_list) = client_receiver.recv() {
-            let client_list_clone = client_list.clone();
-            let streamer = streamer_arc_clone.lock().unwrap();
-            streamer.update_clients(client_list);
+        loop {
+            //senza eventi si tolgono comunque i segni vecchi degli spettatori
+            let event = match client_receiver.recv_timeout(POINTER_DURATION / 4) {
+                Ok(event) => event,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    streamer_arc_clone.lock().unwrap().expire_pointers();
+                    continue;
+                },
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            let mut streamer = streamer_arc_clone.lock().unwrap();
+            match event {
+                ClientEvent::Joined(addr) => {
+                    streamer.add_client(addr);
+                    println!("Client {} connected", addr);
+                },
+                ClientEvent::Left(addr) => {
+                    streamer.remove_client(addr);
+                    println!("Client {} disconnected", addr);
+                },
+                ClientEvent::Pointed(mark) => {
+                    streamer.point(mark);
+                    continue;
+                },
+                ClientEvent::ControlChanged(controller) => {
+                    streamer.set_controller(controller);
+                    continue;
+                },
+                ClientEvent::Input(input) => {
+                    streamer.inject(&input);
+                    continue;
+                },
+            }
 
-            if client_list_clone.is_empty() {
+            if !streamer.has_clients() {
                 println!("No clients connected");
-            } else {
-                println!("Client list updated: {}", client_list_clone);
             }
         }
     });
@@ -84,11 +182,27 @@ fn start_streamer(dimension: DimensionToCrop, num_monitor: usize) -> Result<Stre
         client_thread,
         discovery_thread,
         streamer_arc,
+        admission_receiver,
+        viewers,
+        chat,
+        #[cfg(target_os = "linux")]
+        panic_hotkey,
     })
 }
 
 
+/// Nome dell'utente del sistema, mostrato ai client come presentatore e al presentatore come spettatore
+fn user_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "Presenter".to_string())
+}
+
+
 fn stop_streamer(state: StreamerState) -> Result<(), Box<dyn Error>> {
+    #[cfg(target_os = "linux")]
+    drop(state.panic_hotkey);
+
     // Send a stop message to the control thread
     state.control_sender.send(ControlMessage::Stop)?;
 
@@ -102,14 +216,104 @@ fn stop_streamer(state: StreamerState) -> Result<(), Box<dyn Error>> {
 }
 
 
-fn start_client(ip_addr: IpAddr) -> Result<(StreamerClient, Arc<Mutex<DiscoveryClient>>), Box<dyn Error>> {
-    let discovery_client = Arc::new(Mutex::new(DiscoveryClient::new()?));
-    let (client_ip, client_port) = {
-        let client = discovery_client.lock().unwrap();
-        client.discover_server(ip_addr)?
+/// Pauses the stream and tells the connected clients, returns false if the streamer could not pause
+fn pause_streamer(state: &StreamerState) -> bool {
+    let paused = state.streamer_arc.lock().unwrap().pause();
+    if paused {
+        notify_clients(state, StreamEvent::Paused);
+    }
+    paused
+}
+
+
+fn resume_streamer(state: &StreamerState) -> bool {
+    let resumed = state.streamer_arc.lock().unwrap().un_pause();
+    if resumed {
+        notify_clients(state, StreamEvent::Resumed);
+    }
+    resumed
+}
+
+
+/// Tells the connected clients that the presenter changed something in the stream
+fn notify_clients(state: &StreamerState, event: StreamEvent) {
+    if let Err(e) = state.control_sender.send(ControlMessage::Notify(event)) {
+        println!("Failed to notify {:?} to the clients: {}", event, e);
+    }
+}
+
+
+/// Admits or denies a client of the waiting room
+fn answer_join_request(state: &StreamerState, client: SocketAddr, admitted: bool) {
+    let answer = match admitted {
+        true => ControlMessage::Admit(client),
+        false => ControlMessage::Deny(client),
     };
+    if let Err(e) = state.control_sender.send(answer) {
+        println!("Failed to answer the join request of {}: {}", client, e);
+    }
+}
+
+
+/// Stops sending the stream to a viewer, with `ban` it can't join again for the rest of the session
+fn remove_viewer(state: &StreamerState, client: SocketAddr, ban: bool) {
+    let message = match ban {
+        true => ControlMessage::Ban(client),
+        false => ControlMessage::Kick(client),
+    };
+    if let Err(e) = state.control_sender.send(message) {
+        println!("Failed to remove the viewer {}: {}", client, e);
+    }
+}
+
+
+/// Sends a chat message of the presenter to every viewer
+fn send_chat(state: &StreamerState, text: String) {
+    if let Err(e) = state.control_sender.send(ControlMessage::Chat(text)) {
+        println!("Failed to send the chat message: {}", e);
+    }
+}
+
 
-    let mut player = StreamerClient::new(client_ip.clone(), client_port)?;
+/// Gives the control of the desktop to a viewer that asked for it
+fn grant_control(state: &StreamerState, client: SocketAddr) {
+    if let Err(e) = state.control_sender.send(ControlMessage::GrantControl(client)) {
+        println!("Failed to give the control to {}: {}", client, e);
+    }
+}
+
+
+/// Takes the control back (or refuses the request) of `client`, with `None` of whoever has it
+fn revoke_control(state: &StreamerState, client: Option<SocketAddr>) {
+    if let Err(e) = state.control_sender.send(ControlMessage::RevokeControl(client)) {
+        println!("Failed to revoke the remote control: {}", e);
+    }
+}
+
+
+/// Lets the viewers write in the chat or stops them, the history stays
+fn set_chat_enabled(state: &StreamerState, enabled: bool) {
+    notify_clients(state, if enabled { StreamEvent::ChatEnabled } else { StreamEvent::ChatDisabled });
+}
+
+
+/// `local_port` is where the stream is received, 0 lets the system choose it,
+/// `waiting` is set while the presenter decides whether to admit us
+fn start_client(server_addr: SocketAddr, local_port: u16, pin: Option<String>, nickname: Option<String>, with_audio: bool, waiting: &AtomicBool) -> Result<(StreamerClient, Arc<Mutex<DiscoveryClient>>), Box<dyn Error>> {
+    let discovery_client = Arc::new(Mutex::new(DiscoveryClient::new(local_port)?));
+    let (session, srtp_key) = {
+        let mut client = discovery_client.lock().unwrap();
+        client.set_nickname(nickname);
+        let session = client.discover_server(server_addr, pin.as_deref(), waiting)?;
+        client.start_session_thread()?;
+        (session, client.srtp_key().cloned())
+    };
+
+    let mut player = StreamerClient::new(&session, srtp_key.as_ref(), with_audio)?;
+    if session.remote_control {
+        let client = discovery_client.lock().unwrap();
+        player.set_remote_control(client.control_flag(), client.input_sender()?);
+    }
     player.start_streaming()?;
 
     Ok((player, discovery_client))
@@ -132,7 +336,151 @@ fn stop_client(mut player:StreamerClient, discovery_client: Arc<Mutex<DiscoveryC
 }
 
 
+/// Starts one or more streamers without the GUI, e.g. `--headless --source test` on a machine without display.
+/// Accepted sources: `screen[:monitor]`, `test[:pattern]`, `file:<path>`, `x11[:display]` (linux only).
+/// Every `--source` can be followed by `--port <discovery port>` and `--rtp-port <port>`,
+/// so several sessions run side by side: `--source screen:0 --port 9000 --source screen:1 --port 9002`.
+/// `--pin <pin>` protects the session of the `--source` before it, `--confidential` refuses to send it unencrypted,
+/// `--title <title>` and `--presenter <name>` are what the clients see of it, `--no-chat` keeps the viewers from writing,
+/// `--share-pointers` draws on the stream where the viewers click, `--remote-control` lets the viewers ask
+/// to drive the desktop (`grant <address>` and `revoke` on the console) and `--remote-control-dry-run` accepts it without injecting the input
+fn run_headless(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut sessions: Vec<(&str, StreamConfig)> = Vec::new();
+    let mut index = 0;
+    while index < args.len() {
+        let flag = args[index].as_str();
+        let value = args.get(index + 1).map(|arg| arg.as_str());
+        //le opzioni date prima di ogni --source valgono per una sorgente di test
+        if sessions.is_empty() && matches!(flag, "--pin" | "--confidential" | "--no-chat" | "--share-pointers" | "--remote-control" | "--remote-control-dry-run" | "--port" | "--rtp-port" | "--title" | "--presenter") {
+            sessions.push(("test", StreamConfig::default()));
+        }
+        match (flag, value) {
+            ("--source", Some(source)) => sessions.push((source, StreamConfig::default())),
+            ("--pin", Some(pin)) => sessions.last_mut().unwrap().1.pin = Some(pin.to_string()),
+            ("--title", Some(title)) => sessions.last_mut().unwrap().1.session_name = title.to_string(),
+            ("--presenter", Some(name)) => sessions.last_mut().unwrap().1.presenter_name = name.to_string(),
+            ("--confidential", _) | ("--no-chat", _) | ("--share-pointers", _) | ("--remote-control", _) | ("--remote-control-dry-run", _) => {
+                let config = &mut sessions.last_mut().unwrap().1;
+                match flag {
+                    "--confidential" => config.confidential = true,
+                    "--no-chat" => config.chat_enabled = false,
+                    "--remote-control" => config.remote_control = RemoteControlMode::Enabled,
+                    "--remote-control-dry-run" => config.remote_control = RemoteControlMode::DryRun,
+                    _ => config.share_pointers = true,
+                }
+                index += 1;
+                continue;
+            },
+            ("--port", Some(port)) | ("--rtp-port", Some(port)) => {
+                let config = &mut sessions.last_mut().unwrap().1;
+                let port: u16 = port.parse().map_err(|_| format!("Invalid port '{}'", port))?;
+                if flag == "--port" {
+                    //sulla porta degli annunci il server riceverebbe anche quelli delle altre sessioni
+                    if port == ANNOUNCE_PORT {
+                        return Err(format!("Port {} is reserved for the session announcements", ANNOUNCE_PORT).into());
+                    }
+                    config.discovery_port = port;
+                } else {
+                    config.rtp_port = port;
+                }
+            },
+            _ => {
+                index += 1;
+                continue;
+            },
+        }
+        index += 2;
+    }
+    if sessions.is_empty() {
+        sessions.push(("test", StreamConfig::default()));
+    }
+    //prima di avviare qualsiasi sessione, così non ne resta in piedi solo una parte
+    for (_, config) in &sessions {
+        config.check_confidential().map_err(|e| format!("Session on port {}: {}", config.discovery_port, e))?;
+    }
+
+    let mut states = Vec::new();
+    for (source_arg, config) in sessions {
+        println!("Starting session on port {}", config.discovery_port);
+        states.push(start_streamer(full_screen(), headless_source(source_arg)?, config)?);
+    }
+
+    //la console si legge in un altro thread, così intanto si vede se la sorgente di una sessione è finita
+    let (line_sender, line_receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            if line_sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    println!("Press ENTER to stop the streamer");
+    loop {
+        match line_receiver.recv_timeout(SOURCE_CHECK_INTERVAL) {
+            Ok(line) => match line.trim().split_once(' ') {
+                Some(("grant", client)) => match client.trim().parse() {
+                    Ok(client) => states.iter().for_each(|state| grant_control(state, client)),
+                    Err(_) => println!("Invalid address '{}'", client),
+                },
+                None if line.trim() == "revoke" => states.iter().for_each(|state| revoke_control(state, None)),
+                _ => break,
+            },
+            Err(mpsc::RecvTimeoutError::Timeout) => {},
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        //es. un file arrivato alla fine: i client ricevono SessionEnded e le altre sessioni continuano
+        let (ended, running): (Vec<_>, Vec<_>) = states.into_iter()
+            .partition(|state| state.streamer_arc.lock().unwrap().source_ended());
+        states = running;
+        for state in ended {
+            println!("The source of a session ended, stopping it");
+            stop_streamer(state)?;
+        }
+        if states.is_empty() {
+            return Ok(());
+        }
+    }
+
+    for state in states {
+        stop_streamer(state)?;
+    }
+    Ok(())
+}
+
+
+fn headless_source(source_arg: &str) -> Result<Box<dyn CaptureSource>, Box<dyn Error>> {
+    let source: Box<dyn CaptureSource> = match source_arg.split_once(':') {
+        Some(("file", path)) => Box::new(FileSource { path: path.into() }),
+        Some(("test", pattern)) => Box::new(TestSource { pattern: pattern.to_string() }),
+        Some(("screen", monitor)) => capture::screen_source(monitor.parse()?),
+        #[cfg(target_os = "linux")]
+        Some(("x11", display)) => Box::new(capture::XImageSource { display_name: Some(display.to_string()), ..Default::default() }),
+        #[cfg(target_os = "linux")]
+        None if source_arg == "x11" => Box::new(capture::XImageSource::default()),
+        None if source_arg == "test" => Box::new(TestSource::default()),
+        None if source_arg == "screen" => capture::screen_source(0),
+        _ => return Err(format!("Unknown source '{}'", source_arg).into()),
+    };
+    Ok(source)
+}
+
+
+fn full_screen() -> DimensionToCrop {
+    DimensionToCrop { top: 0, bottom: 0, right: 0, left: 0 }
+}
+
+
 fn main()  {
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|arg| arg == "--headless") {
+        if let Err(e) = run_headless(&args) {
+            println!("Error running headless streamer: {}", e);
+        }
+        return;
+    }
+
     let res = run_iced();
 
     match res {
@@ -0,0 +1,167 @@
+use std::net::SocketAddr;
+use gst::{Element, Pipeline, prelude::*};
+use crate::streamer::config::AudioConfig;
+use crate::streamer::error::ServerError;
+use crate::streamer::srtp::SrtpKey;
+use crate::streamer::streamer::{self, AUDIO_SESSION, RTCP_PORT_OFFSET};
+
+
+const AUDIO_PAYLOAD_TYPE: u32 = 97;
+const OPUS_BITRATE: i32 = 96000;
+
+//Nomi degli elementi volume, usati per il mute durante lo streaming
+pub const SYSTEM_VOLUME: &str = "system_volume";
+pub const MICROPHONE_VOLUME: &str = "microphone_volume";
+
+
+/// Audio played by the presenter's machine (monitor of the default output)
+fn system_audio_source() -> Result<Element, ServerError> {
+    #[cfg(target_os = "linux")]
+    let builder = gst::ElementFactory::make("pulsesrc")
+        .property("device", "@DEFAULT_MONITOR@");
+
+    #[cfg(target_os = "windows")]
+    let builder = gst::ElementFactory::make("wasapi2src")
+        .property("loopback", true);
+
+    #[cfg(target_os = "macos")]
+    return Err(ServerError {
+        message: "System audio capture is not supported on macOS".to_string(),
+    });
+
+    #[cfg(not(target_os = "macos"))]
+    builder
+        .property("do-timestamp", true)
+        .build()
+        .map_err(|_| ServerError {
+            message: "Failed to create the system audio source".to_string(),
+        })
+}
+
+fn microphone_source() -> Result<Element, ServerError> {
+    gst::ElementFactory::make("autoaudiosrc")
+        .build()
+        .map_err(|_| ServerError {
+            message: "Failed to create the microphone source".to_string(),
+        })
+}
+
+
+/// Adds `source ! audioconvert ! audioresample ! volume` to the pipeline and links it to the mixer
+fn add_audio_input(pipeline: &Pipeline, source: Element, volume_name: &str, mixer: &Element) -> Result<(), ServerError> {
+    let audioconvert = gst::ElementFactory::make("audioconvert").build()
+        .map_err(|_| ServerError {
+            message: "Failed to create audioconvert".to_string(),
+        })?;
+
+    let audioresample = gst::ElementFactory::make("audioresample").build()
+        .map_err(|_| ServerError {
+            message: "Failed to create audioresample".to_string(),
+        })?;
+
+    let volume = gst::ElementFactory::make("volume")
+        .name(volume_name)
+        .build()
+        .map_err(|_| ServerError {
+            message: "Failed to create volume".to_string(),
+        })?;
+
+    pipeline.add_many(&[&source, &audioconvert, &audioresample, &volume]).map_err(|_| ServerError {
+        message: "Failed to add audio input to pipeline".to_string(),
+    })?;
+
+    //il link verso audiomixer richiede da solo un nuovo pad sink_%u
+    gst::Element::link_many(&[&source, &audioconvert, &audioresample, &volume, mixer]).map_err(|_| ServerError {
+        message: format!("Failed to link audio input {}", volume_name),
+    })?;
+
+    Ok(())
+}
+
+
+/// Builds the audio half of the pipeline: the enabled sources are mixed, encoded with Opus
+/// and sent by `multiudpsink_audio` on a second session of `rtpbin`, to the port after the video one.
+/// The sender reports of the session go to `multiudpsink_audio_rtcp`, so the clients can synchronize audio and video.
+/// If `video_rtp_port` is set the audio also leaves from the port after it, with `srtp_key` it is encrypted like the video
+pub fn add_audio_branch(pipeline: &Pipeline, rtpbin: &Element, audio: &AudioConfig, video_rtp_port: u16, srtp_key: Option<&SrtpKey>) -> Result<(), ServerError> {
+    let audiomixer = gst::ElementFactory::make("audiomixer").build()
+        .map_err(|_| ServerError {
+            message: "Failed to create audiomixer".to_string(),
+        })?;
+
+    let audioconvert = gst::ElementFactory::make("audioconvert").build()
+        .map_err(|_| ServerError {
+            message: "Failed to create audioconvert".to_string(),
+        })?;
+
+    let opusenc = gst::ElementFactory::make("opusenc")
+        .property("bitrate", OPUS_BITRATE)
+        .build()
+        .map_err(|_| ServerError {
+            message: "Failed to create opusenc".to_string(),
+        })?;
+
+    let rtpopuspay = gst::ElementFactory::make("rtpopuspay")
+        .property("pt", AUDIO_PAYLOAD_TYPE)
+        .build()
+        .map_err(|_| ServerError {
+            message: "Failed to create rtpopuspay".to_string(),
+        })?;
+
+    let queue = gst::ElementFactory::make("queue").build()
+        .map_err(|_| ServerError {
+            message: "Failed to create audio queue".to_string(),
+        })?;
+
+    let bind_port = streamer::source_port(video_rtp_port, 1)?;
+
+    let udpsink = gst::ElementFactory::make("multiudpsink")
+        .property("clients", "")
+        .property("bind-port", bind_port as i32)
+        .name("multiudpsink_audio")
+        .build()
+        .map_err(|_| ServerError {
+            message: "Failed to create multiudpsink for audio".to_string(),
+        })?;
+
+    let rtcp_sink = streamer::rtcp_sink("multiudpsink_audio_rtcp", streamer::source_port(video_rtp_port, 1 + RTCP_PORT_OFFSET)?)?;
+
+    let elements = [&audiomixer, &audioconvert, &opusenc, &rtpopuspay, &queue];
+    pipeline.add_many(elements).map_err(|_| ServerError {
+        message: "Failed to add audio elements to pipeline".to_string(),
+    })?;
+    pipeline.add_many([&udpsink, &rtcp_sink]).map_err(|_| ServerError {
+        message: "Failed to add the audio multiudpsinks to pipeline".to_string(),
+    })?;
+
+    gst::Element::link_many(elements).map_err(|_| ServerError {
+        message: "Failed to link audio elements".to_string(),
+    })?;
+    streamer::link_rtp_session(pipeline, rtpbin, AUDIO_SESSION, &queue, &udpsink, &rtcp_sink, srtp_key)?;
+
+    if audio.system_audio {
+        add_audio_input(pipeline, system_audio_source()?, SYSTEM_VOLUME, &audiomixer)?;
+    }
+    if audio.microphone {
+        add_audio_input(pipeline, microphone_source()?, MICROPHONE_VOLUME, &audiomixer)?;
+    }
+
+    Ok(())
+}
+
+
+/// Caps of the RTP stream produced by `add_audio_branch`, sent to the clients when they join
+pub fn rtp_caps() -> gst::Caps {
+    gst::Caps::builder("application/x-rtp")
+        .field("media", "audio")
+        .field("clock-rate", 48000)
+        .field("encoding-name", "OPUS")
+        .field("payload", AUDIO_PAYLOAD_TYPE as i32)
+        .build()
+}
+
+
+/// Address where a client receives the audio: same host, video port + 1
+pub fn audio_address(video_address: SocketAddr) -> Option<SocketAddr> {
+    Some(SocketAddr::new(video_address.ip(), video_address.port().checked_add(1)?))
+}
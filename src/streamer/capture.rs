@@ -0,0 +1,372 @@
+use std::fmt;
+use std::path::PathBuf;
+use gst::{Element, Pipeline, prelude::*};
+use crate::streamer::error::ServerError;
+
+
+/// Something that produces the raw video sent by `ScreenStreamer`.
+///
+/// `build` adds its element(s) to the pipeline, links them together and returns the last one:
+/// the rest of the pipeline (crop, encoder, payloader...) is linked to its src pad.
+pub trait CaptureSource: Send {
+    fn name(&self) -> String;
+    fn build(&self, pipeline: &Pipeline) -> Result<Element, ServerError>;
+    /// Position of the top-left captured pixel on the desktop, `None` if it is not a screen
+    fn screen_origin(&self) -> Option<(i32, i32)> {
+        None
+    }
+}
+
+
+fn add_source(pipeline: &Pipeline, element: Element, name: &str) -> Result<Element, ServerError> {
+    pipeline.add(&element).map_err(|_| ServerError {
+        message: format!("Failed to add {} to pipeline", name),
+    })?;
+    Ok(element)
+}
+
+
+/// Monitor o finestra condivisa tramite il portale, identificata dal nodo PipeWire
+#[cfg(target_os = "linux")]
+pub struct PipeWireSource {
+    pub node_id: u32,
+    pub origin: Option<(i32, i32)>, // posizione data dal portale, solo per i monitor
+}
+
+#[cfg(target_os = "linux")]
+impl CaptureSource for PipeWireSource {
+    fn name(&self) -> String {
+        format!("PipeWire node {}", self.node_id)
+    }
+
+    fn build(&self, pipeline: &Pipeline) -> Result<Element, ServerError> {
+        let videosrc = gst::ElementFactory::make("pipewiresrc")
+            .property("path", self.node_id.to_string())
+            .property("do-timestamp", true)
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create pipewiresrc".to_string(),
+            })?;
+
+        add_source(pipeline, videosrc, "pipewiresrc")
+    }
+
+    fn screen_origin(&self) -> Option<(i32, i32)> {
+        self.origin
+    }
+}
+
+
+/// Capture of an X11 display without going through the xdg-desktop-portal.
+/// `region` is (startx, starty, endx, endy) in screen coordinates, both ends included
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+pub struct XImageSource {
+    pub display_name: Option<String>,
+    pub region: Option<(u32, u32, u32, u32)>,
+    pub use_damage: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl XImageSource {
+    /// Captures only one monitor of the X screen, given its position and size. `desktop_origin` is the top-left
+    /// corner of all the monitors together in the same coordinates: the root window of X starts there
+    pub fn for_monitor(x: i32, y: i32, width: u32, height: u32, desktop_origin: (i32, i32)) -> Result<Self, ServerError> {
+        let region = root_region(x, y, width, height, desktop_origin).ok_or_else(|| ServerError {
+            message: format!("The monitor at {},{} ({}x{}) is not on the X screen, it may be disconnected", x, y, width, height),
+        })?;
+        Ok(Self {
+            display_name: std::env::var("DISPLAY").ok(),
+            region: Some(region),
+            //con use-damage ximagesrc legge solo le zone cambiate ma con molti aggiornamenti è più lento
+            use_damage: false,
+        })
+    }
+}
+
+/// Region of ximagesrc, in coordinates of the root window, for a monitor placed at `x`,`y` on a desktop that
+/// starts at `desktop_origin`. None if the monitor has no size or is outside of the desktop
+#[cfg(target_os = "linux")]
+pub fn root_region(x: i32, y: i32, width: u32, height: u32, desktop_origin: (i32, i32)) -> Option<(u32, u32, u32, u32)> {
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let startx = u32::try_from(x.checked_sub(desktop_origin.0)?).ok()?;
+    let starty = u32::try_from(y.checked_sub(desktop_origin.1)?).ok()?;
+    Some((startx, starty, startx.checked_add(width - 1)?, starty.checked_add(height - 1)?))
+}
+
+#[cfg(target_os = "linux")]
+impl CaptureSource for XImageSource {
+    fn name(&self) -> String {
+        format!("X11 display {}", self.display_name.as_deref().unwrap_or("default"))
+    }
+
+    fn build(&self, pipeline: &Pipeline) -> Result<Element, ServerError> {
+        let mut builder = gst::ElementFactory::make("ximagesrc")
+            .property("show-pointer", true)
+            .property("do-timestamp", true)
+            .property("use-damage", self.use_damage);
+        if let Some(ref display_name) = self.display_name {
+            builder = builder.property("display-name", display_name);
+        }
+        if let Some((startx, starty, endx, endy)) = self.region {
+            builder = builder
+                .property("startx", startx)
+                .property("starty", starty)
+                .property("endx", endx)
+                .property("endy", endy);
+        }
+        let videosrc = builder.build().map_err(|_| ServerError {
+            message: "Failed to create ximagesrc".to_string(),
+        })?;
+
+        add_source(pipeline, videosrc, "ximagesrc")
+    }
+
+    /// None for the whole X screen: it may be another display than the one of the injector,
+    /// and with more monitors the frame also has the areas not covered by any of them
+    fn screen_origin(&self) -> Option<(i32, i32)> {
+        let (startx, starty, _, _) = self.region?;
+        Some((startx as i32, starty as i32))
+    }
+}
+
+
+/// True if there is an X server to capture from, used when the screencast portal is missing
+#[cfg(target_os = "linux")]
+pub fn x11_available() -> bool {
+    std::env::var_os("DISPLAY").is_some()
+}
+
+
+#[cfg(target_os = "windows")]
+pub struct D3D11Source {
+    pub monitor_index: usize,
+}
+
+#[cfg(target_os = "windows")]
+impl CaptureSource for D3D11Source {
+    fn name(&self) -> String {
+        format!("Monitor {}", self.monitor_index)
+    }
+
+    fn build(&self, pipeline: &Pipeline) -> Result<Element, ServerError> {
+        let videosrc = gst::ElementFactory::make("d3d11screencapturesrc")
+            .property("show-cursor", true)
+            .property("monitor-index", self.monitor_index as i32)
+            //.property("show-border", true)
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create d3d11screencapturesrc".to_string(),
+            })?;
+
+        add_source(pipeline, videosrc, "d3d11screencapturesrc")
+    }
+
+    fn screen_origin(&self) -> Option<(i32, i32)> {
+        monitor_origin(self.monitor_index)
+    }
+}
+
+
+#[cfg(target_os = "macos")]
+pub struct AvfSource {
+    pub monitor_index: usize,
+}
+
+#[cfg(target_os = "macos")]
+impl CaptureSource for AvfSource {
+    fn name(&self) -> String {
+        format!("Monitor {}", self.monitor_index)
+    }
+
+    fn build(&self, pipeline: &Pipeline) -> Result<Element, ServerError> {
+        let videosrc = gst::ElementFactory::make("avfvideosrc")
+            .property("capture-screen", true)
+            .property("device-index", self.monitor_index as i32)
+            .build()
+            .map_err(|_| ServerError { message: "Failed to create avfvideosrc".to_string()})?;
+
+        add_source(pipeline, videosrc, "avfvideosrc")
+    }
+
+    fn screen_origin(&self) -> Option<(i32, i32)> {
+        monitor_origin(self.monitor_index)
+    }
+}
+
+
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+fn monitor_origin(monitor_index: usize) -> Option<(i32, i32)> {
+    screenshots::Screen::all()
+        .ok()?
+        .get(monitor_index)
+        .map(|screen| (screen.display_info.x, screen.display_info.y))
+}
+
+
+/// Streams a media file instead of the screen, decoded in real time
+pub struct FileSource {
+    pub path: PathBuf,
+}
+
+impl CaptureSource for FileSource {
+    fn name(&self) -> String {
+        format!("File {}", self.path.display())
+    }
+
+    fn build(&self, pipeline: &Pipeline) -> Result<Element, ServerError> {
+        let location = self.path.to_str().ok_or(ServerError {
+            message: format!("Invalid file path {}", self.path.display()),
+        })?;
+
+        let filesrc = gst::ElementFactory::make("filesrc")
+            .property("location", location)
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create filesrc".to_string(),
+            })?;
+
+        let decodebin = gst::ElementFactory::make("decodebin").build()
+            .map_err(|_| ServerError {
+                message: "Failed to create decodebin".to_string(),
+            })?;
+
+        let videoconvert = gst::ElementFactory::make("videoconvert").build()
+            .map_err(|_| ServerError {
+                message: "Failed to create videoconvert for the file source".to_string(),
+            })?;
+
+        pipeline.add_many(&[&filesrc, &decodebin, &videoconvert]).map_err(|_| ServerError {
+            message: "Failed to add file source elements to pipeline".to_string(),
+        })?;
+
+        filesrc.link(&decodebin).map_err(|_| ServerError {
+            message: "Failed to link filesrc to decodebin".to_string(),
+        })?;
+
+        //decodebin crea i pad solo dopo aver riconosciuto il file, si collega solo quello video
+        let videoconvert_weak = videoconvert.downgrade();
+        decodebin.connect_pad_added(move |_, src_pad| {
+            let Some(videoconvert) = videoconvert_weak.upgrade() else {
+                return;
+            };
+            let is_video = src_pad
+                .current_caps()
+                .and_then(|caps| caps.structure(0).map(|s| s.name().starts_with("video/")))
+                .unwrap_or(false);
+            let sink_pad = videoconvert.static_pad("sink").unwrap();
+            if is_video && !sink_pad.is_linked() {
+                if let Err(e) = src_pad.link(&sink_pad) {
+                    println!("Failed to link decodebin to videoconvert: {:?}", e);
+                }
+            }
+        });
+
+        Ok(videoconvert)
+    }
+}
+
+
+/// Synthetic source, useful on machines without a display or a portal
+pub struct TestSource {
+    pub pattern: String,
+}
+
+impl Default for TestSource {
+    fn default() -> Self {
+        Self {
+            pattern: "smpte".to_string(),
+        }
+    }
+}
+
+impl CaptureSource for TestSource {
+    fn name(&self) -> String {
+        format!("Test pattern {}", self.pattern)
+    }
+
+    fn build(&self, pipeline: &Pipeline) -> Result<Element, ServerError> {
+        let videosrc = gst::ElementFactory::make("videotestsrc")
+            .property("is-live", true)
+            .property_from_str("pattern", &self.pattern)
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create videotestsrc".to_string(),
+            })?;
+
+        add_source(pipeline, videosrc, "videotestsrc")
+    }
+}
+
+
+/// Screen capture source of the current OS for the given monitor (PipeWire node on linux)
+pub fn screen_source(monitor_index: usize) -> Box<dyn CaptureSource> {
+    #[cfg(target_os = "linux")]
+    return Box::new(PipeWireSource { node_id: monitor_index as u32, origin: None });
+
+    #[cfg(target_os = "windows")]
+    return Box::new(D3D11Source { monitor_index });
+
+    #[cfg(target_os = "macos")]
+    return Box::new(AvfSource { monitor_index });
+}
+
+
+/// Type of source chosen on the streamer screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureKind {
+    Screen,
+    TestPattern,
+    MediaFile,
+}
+
+impl CaptureKind {
+    pub const ALL: [CaptureKind; 3] = [
+        CaptureKind::Screen,
+        CaptureKind::TestPattern,
+        CaptureKind::MediaFile,
+    ];
+}
+
+impl fmt::Display for CaptureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureKind::Screen => write!(f, "Source: screen"),
+            CaptureKind::TestPattern => write!(f, "Source: test pattern"),
+            CaptureKind::MediaFile => write!(f, "Source: media file"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn monitors_are_placed_on_the_root_window() {
+        assert_eq!(root_region(0, 0, 1920, 1080, (0, 0)), Some((0, 0, 1919, 1079)));
+        // monitor a sinistra del principale: la root window parte da lui
+        assert_eq!(root_region(-1280, 0, 1280, 1024, (-1280, 0)), Some((0, 0, 1279, 1023)));
+        assert_eq!(root_region(0, 0, 1920, 1080, (-1280, 0)), Some((1280, 0, 3199, 1079)));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn empty_or_misplaced_monitors_are_rejected() {
+        assert_eq!(root_region(0, 0, 0, 1080, (0, 0)), None);
+        assert_eq!(root_region(0, 0, 1920, 0, (0, 0)), None);
+        assert_eq!(root_region(-10, 0, 1920, 1080, (0, 0)), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn only_a_monitor_has_a_position_on_the_desktop() {
+        assert_eq!(XImageSource::default().screen_origin(), None);
+        let monitor = XImageSource::for_monitor(0, 0, 1920, 1080, (-1280, 0)).unwrap();
+        assert_eq!(monitor.screen_origin(), Some((1280, 0)));
+    }
+}
@@ -3,8 +3,14 @@ use rfd::FileDialog;
 use gst::{ClockTime, Element, Pipeline, State};
 use std::{thread};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use crate::streamer::error::ClientError;
+use crate::connection::client::InputSender;
+use crate::connection::protocol::{InputEvent, SessionInfo};
+use crate::streamer::srtp::SrtpKey;
+use crate::streamer::streamer::{AUDIO_SESSION, RTCP_PORT_OFFSET, VIDEO_SESSION};
+use std::str::FromStr;
 #[cfg(target_os = "macos")]
 use objc::{class, msg_send, sel, sel_impl};
 
@@ -20,15 +26,43 @@ fn initialize_macos_app() {
 }
 
 
+//Latenza dei jitterbuffer di rtpbin, che allinea audio e video con i sender report RTCP
+const JITTER_LATENCY_MS: u32 = 200;
+const BUS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+//i movimenti del mouse si mandano al massimo a 60 al secondo
+const MOUSE_MOVE_INTERVAL: Duration = Duration::from_millis(16);
+
+
+/// Decoded frame of the stream, drawn by the GUI
+#[derive(Debug, Clone)]
+pub struct VideoFrame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>, // RGBA, una riga dopo l'altra senza padding
+}
+
+
+/// Where the input on the video goes while the presenter lets us control the desktop
+struct RemoteInput {
+    controlling: Arc<AtomicBool>,
+    sender: InputSender,
+    last_move: Option<Instant>,
+}
+
+
 pub struct StreamerClient {
     pipeline: Option<Pipeline>,
     is_streaming: Arc<Mutex<bool>>,
     is_recording: Arc<Mutex<bool>>,
-    tee: Option<Element> //Allows streaming and recording to happen simultaneously in 2 different pipelines
+    tee: Option<Element>, //Allows streaming and recording to happen simultaneously in 2 different pipelines
+    frame: Arc<Mutex<Option<VideoFrame>>>, // ultimo frame decodificato, None dopo take_frame
+    remote_input: Mutex<Option<RemoteInput>>,
 }
 
 impl StreamerClient {
-    pub fn new(ip: String, port: i32) -> Result<Self, ClientError> {
+    /// Builds the pipeline that receives the stream described by `session`, the caps come from the server.
+    /// `srtp_key` is the one decrypted by `DiscoveryClient`, None if the stream is unencrypted
+    pub fn new(session: &SessionInfo, srtp_key: Option<&SrtpKey>, with_audio: bool) -> Result<Self, ClientError> {
         gst::init().unwrap();
 
         //obbligatorio per macos, obbliga a riprodurre sul thread principale
@@ -37,17 +71,30 @@ impl StreamerClient {
             initialize_macos_app();
         }
 
-        println!("IP:{} Port: {}", ip,port);
+        let ip = &session.client_ip;
+        let port = session.stream_port as i32;
+
+        //per ora si sa decodificare solo H264
+        if session.codec != "H264" {
+            return Err(ClientError { message: format!("Unsupported codec '{}'", session.codec) });
+        }
+        let video_caps = gst::Caps::from_str(&session.video_caps)
+            .map_err(|_| ClientError { message: format!("Invalid video caps '{}'", session.video_caps) })?;
+        if srtp_key.is_none() {
+            println!("The server sends the stream unencrypted");
+        }
 
         let pipeline = Pipeline::new();
 
-        let udpsrc = gst::ElementFactory::make("udpsrc")
-            .property("port", &port)
-            .property("address", &ip)
-            .property("caps", &gst::Caps::new_empty_simple("application/x-rtp"))
+        let rtpbin = gst::ElementFactory::make("rtpbin")
+            .name("rtpbin")
+            .property("latency", JITTER_LATENCY_MS)
             .build()
-            .map_err(|_| ClientError { message: "Failed to create element 'udpsrc'".to_string() })?;
+            .map_err(|_| ClientError { message: "Failed to create element 'rtpbin'".to_string() })?;
+        pipeline.add(&rtpbin)
+            .map_err(|_| ClientError { message: "Failed to add rtpbin to pipeline".to_string() })?;
 
+        Self::add_rtp_session(&pipeline, &rtpbin, VIDEO_SESSION, ip, port, &video_caps, srtp_key)?;
 
         let queue = gst::ElementFactory::make("queue").build()
             .map_err(|_| ClientError {
@@ -77,32 +124,55 @@ impl StreamerClient {
             .build()
             .map_err(|_| ClientError { message: "Failed to create element 'videoconvert'".to_string() })?;
 
-        let autovideosink = gst::ElementFactory::make("autovideosink")
-            .property("sync", true)  
+        //il video si disegna nella finestra dell'applicazione, che vuole RGBA
+        let rgba_caps = gst::ElementFactory::make("capsfilter")
+            .property("caps", gst::Caps::builder("video/x-raw").field("format", "RGBA").build())
             .build()
-            .map_err(|_| ClientError { message: "Failed to create element 'autovideosink'".to_string() })?;
+            .map_err(|_| ClientError { message: "Failed to create element 'capsfilter'".to_string() })?;
+
+        let appsink = gst::ElementFactory::make("appsink")
+            .name("videosink")
+            .property("sync", true)
+            .property("emit-signals", true)
+            .property("max-buffers", 1u32)
+            .property("drop", true)
+            .build()
+            .map_err(|_| ClientError { message: "Failed to create element 'appsink'".to_string() })?;
 
         pipeline.add_many(&[
-            &udpsrc,
             &queue,
             &rtph264depay,
             &tee,
             &queue_display,
             &avdec_h264,
             &videoconvert,
-            &autovideosink,
+            &rgba_caps,
+            &appsink,
         ]).map_err(|_| ClientError { message: "Failed to add elements to pipeline".to_string() })?;
 
         gst::Element::link_many(&[
-            &udpsrc,
             &queue,
             &rtph264depay,
             &tee,
             &queue_display,
             &avdec_h264,
             &videoconvert,
-            &autovideosink,
+            &rgba_caps,
+            &appsink,
         ]).map_err(|_| ClientError { message: "Failed to link elements".to_string() })?;
+        Self::link_session_output(&rtpbin, VIDEO_SESSION, &queue);
+
+        match session.audio_caps {
+            Some(ref audio_caps) if with_audio => {
+                let audio_caps = gst::Caps::from_str(audio_caps)
+                    .map_err(|_| ClientError { message: format!("Invalid audio caps '{}'", audio_caps) })?;
+                Self::add_audio_branch(&pipeline, &rtpbin, ip, port + 1, &audio_caps, srtp_key)?;
+            },
+            _ => {},
+        }
+
+        let frame = Arc::new(Mutex::new(None));
+        Self::collect_frames(&appsink, Arc::clone(&frame));
 
         pipeline.set_state(State::Ready).expect("Unable to set the pipeline to the `Ready` state");
 
@@ -111,9 +181,188 @@ impl StreamerClient {
             is_streaming: Arc::new(Mutex::new(false)),
             is_recording: Arc::new(Mutex::new(false)),
             tee: Some(tee),
+            frame,
+            remote_input: Mutex::new(None),
         })
     }
 
+    /// Keeps the last frame appsink receives, the GUI takes it with `take_frame`
+    fn collect_frames(appsink: &Element, frame: Arc<Mutex<Option<VideoFrame>>>) {
+        //un riferimento debole, altrimenti appsink terrebbe in vita se stesso
+        let weak_sink = appsink.downgrade();
+        appsink.connect("new-sample", false, move |_| {
+            let sample = weak_sink.upgrade()
+                .and_then(|appsink| appsink.emit_by_name::<Option<gst::Sample>>("pull-sample", &[]));
+            if let Some(decoded) = sample.as_ref().and_then(Self::video_frame) {
+                *frame.lock().unwrap() = Some(decoded);
+            }
+            Some(gst::FlowReturn::Ok.to_value())
+        });
+    }
+
+    fn video_frame(sample: &gst::Sample) -> Option<VideoFrame> {
+        let structure = sample.caps()?.structure(0)?;
+        let (width, height) = (structure.get::<i32>("width").ok()?, structure.get::<i32>("height").ok()?);
+        let buffer = sample.buffer()?.map_readable().ok()?;
+        //in RGBA ogni riga è già allineata a 4 byte, quindi non c'è padding
+        let size = width as usize * height as usize * 4;
+        if width <= 0 || height <= 0 || buffer.len() < size {
+            return None;
+        }
+        Some(VideoFrame { width: width as u32, height: height as u32, pixels: buffer[..size].to_vec() })
+    }
+
+    /// The frame decoded since the last call, `None` if there is nothing new to draw
+    pub fn take_frame(&self) -> Option<VideoFrame> {
+        self.frame.lock().unwrap().take()
+    }
+
+    /// `controlling` is set by the `DiscoveryClient` while the presenter lets us drive the desktop,
+    /// meanwhile `send_input` goes through `sender`
+    pub fn set_remote_control(&self, controlling: Arc<AtomicBool>, sender: InputSender) {
+        *self.remote_input.lock().unwrap() = Some(RemoteInput { controlling, sender, last_move: None });
+    }
+
+    /// Sends what the viewer does on the video to the presenter, only while we control the desktop.
+    /// The mouse moves are throttled to `MOUSE_MOVE_INTERVAL`
+    pub fn send_input(&self, input: InputEvent) {
+        let mut remote_input = self.remote_input.lock().unwrap();
+        let Some(remote) = remote_input.as_mut().filter(|remote| remote.controlling.load(Ordering::Relaxed)) else {
+            return;
+        };
+        if let InputEvent::MouseMove { .. } = input {
+            if remote.last_move.is_some_and(|sent| sent.elapsed() < MOUSE_MOVE_INTERVAL) {
+                return;
+            }
+            remote.last_move = Some(Instant::now());
+        }
+        if let Err(e) = remote.sender.send(input) {
+            println!("Failed to send the input to the presenter: {}", e);
+        }
+    }
+
+
+    /// udpsrc for the RTP packets of `session` on `port` and for its RTCP sender reports, linked to rtpbin
+    /// through srtpdec if the server encrypts them
+    fn add_rtp_session(pipeline: &Pipeline, rtpbin: &Element, session: u32, ip: &str, port: i32, caps: &gst::Caps,
+        srtp_key: Option<&SrtpKey>) -> Result<(), ClientError> {
+        let media = format!("RTP session {}", session);
+        let (rtp_caps, rtcp_caps) = match srtp_key {
+            Some(key) => (key.secure_caps(caps), gst::Caps::builder("application/x-srtcp").build()),
+            None => (caps.clone(), gst::Caps::builder("application/x-rtcp").build()),
+        };
+        let rtp_src = Self::udp_source(ip, port, &rtp_caps, &media)?;
+        let rtcp_src = Self::udp_source(ip, port + RTCP_PORT_OFFSET as i32, &rtcp_caps, &media)?;
+        pipeline.add_many([&rtp_src, &rtcp_src])
+            .map_err(|_| ClientError { message: format!("Failed to add the udpsrc for {} to pipeline", media) })?;
+
+        let rtp_sink = format!("recv_rtp_sink_{}", session);
+        let rtcp_sink = format!("recv_rtcp_sink_{}", session);
+        match srtp_key {
+            Some(key) => {
+                let srtpdec = gst::ElementFactory::make("srtpdec")
+                    .build()
+                    .map_err(|_| ClientError { message: format!("Failed to create element 'srtpdec' for {}", media) })?;
+                //srtpdec usa le chiavi delle caps solo se hanno l'ssrc, altrimenti le chiede per ogni flusso nuovo
+                let key_caps = key.secure_caps(caps);
+                srtpdec.connect("request-key", false, move |_| Some(key_caps.to_value()));
+                pipeline.add(&srtpdec)
+                    .map_err(|_| ClientError { message: format!("Failed to add srtpdec for {} to pipeline", media) })?;
+                rtp_src.link_pads(None, &srtpdec, Some("rtp_sink"))
+                    .and_then(|_| srtpdec.link_pads(Some("rtp_src"), rtpbin, Some(&rtp_sink)))
+                    .and_then(|_| rtcp_src.link_pads(None, &srtpdec, Some("rtcp_sink")))
+                    .and_then(|_| srtpdec.link_pads(Some("rtcp_src"), rtpbin, Some(&rtcp_sink)))
+            },
+            None => rtp_src.link_pads(None, rtpbin, Some(&rtp_sink))
+                .and_then(|_| rtcp_src.link_pads(None, rtpbin, Some(&rtcp_sink))),
+        }.map_err(|_| ClientError { message: format!("Failed to link {} to rtpbin", media) })
+    }
+
+    fn udp_source(ip: &str, port: i32, caps: &gst::Caps, media: &str) -> Result<Element, ClientError> {
+        gst::ElementFactory::make("udpsrc")
+            .property("port", port)
+            .property("address", ip)
+            .property("caps", caps)
+            .build()
+            .map_err(|_| ClientError { message: format!("Failed to create element 'udpsrc' for {}", media) })
+    }
+
+    /// rtpbin adds the pad of a session when the first packet of the server arrives, then it goes to `first`
+    fn link_session_output(rtpbin: &Element, session: u32, first: &Element) {
+        let prefix = format!("recv_rtp_src_{}_", session);
+        let weak_first = first.downgrade();
+        rtpbin.connect_pad_added(move |_, pad| {
+            if !pad.name().starts_with(&prefix) {
+                return;
+            }
+            //un secondo ssrc nella stessa sessione non ha dove andare
+            match weak_first.upgrade().and_then(|first| first.static_pad("sink")) {
+                Some(sink) if !sink.is_linked() => {
+                    if let Err(e) = pad.link(&sink) {
+                        println!("Failed to link {}: {:?}", pad.name(), e);
+                    }
+                },
+                _ => {},
+            }
+        });
+    }
+
+    /// Receives the Opus stream sent by the server on the port after the video one, in its own session of `rtpbin`:
+    /// with the RTCP sender reports of both sessions rtpbin plays audio and video in sync
+    fn add_audio_branch(pipeline: &Pipeline, rtpbin: &Element, ip: &str, port: i32, caps: &gst::Caps, srtp_key: Option<&SrtpKey>) -> Result<(), ClientError> {
+        Self::add_rtp_session(pipeline, rtpbin, AUDIO_SESSION, ip, port, caps, srtp_key)?;
+
+        let rtpopusdepay = gst::ElementFactory::make("rtpopusdepay")
+            .build()
+            .map_err(|_| ClientError { message: "Failed to create element 'rtpopusdepay'".to_string() })?;
+
+        let opusdec = gst::ElementFactory::make("opusdec")
+            .build()
+            .map_err(|_| ClientError { message: "Failed to create element 'opusdec'".to_string() })?;
+
+        let audioconvert = gst::ElementFactory::make("audioconvert")
+            .build()
+            .map_err(|_| ClientError { message: "Failed to create element 'audioconvert'".to_string() })?;
+
+        let audioresample = gst::ElementFactory::make("audioresample")
+            .build()
+            .map_err(|_| ClientError { message: "Failed to create element 'audioresample'".to_string() })?;
+
+        let volume = gst::ElementFactory::make("volume")
+            .name("client_volume")
+            .build()
+            .map_err(|_| ClientError { message: "Failed to create element 'volume'".to_string() })?;
+
+        let autoaudiosink = gst::ElementFactory::make("autoaudiosink")
+            .property("sync", true)
+            .build()
+            .map_err(|_| ClientError { message: "Failed to create element 'autoaudiosink'".to_string() })?;
+
+        let elements = [
+            &rtpopusdepay,
+            &opusdec,
+            &audioconvert,
+            &audioresample,
+            &volume,
+            &autoaudiosink,
+        ];
+
+        pipeline.add_many(elements)
+            .map_err(|_| ClientError { message: "Failed to add audio elements to pipeline".to_string() })?;
+
+        gst::Element::link_many(elements)
+            .map_err(|_| ClientError { message: "Failed to link audio elements".to_string() })?;
+        Self::link_session_output(rtpbin, AUDIO_SESSION, &rtpopusdepay);
+
+        Ok(())
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        if let Some(volume) = self.pipeline.as_ref().and_then(|pipeline| pipeline.by_name("client_volume")) {
+            volume.set_property("mute", muted);
+        }
+    }
+
 
     pub fn get_is_rec(&self)-> bool{
 
@@ -134,15 +383,13 @@ impl StreamerClient {
             let bus = pipeline.bus().unwrap();
             let is_streaming = Arc::clone(&self.is_streaming);
             let pipeline_clone = self.pipeline.clone();
+            *self.is_streaming.lock().unwrap() = true;
 
             thread::spawn(move || {
-                let timeout = Duration::from_secs(30000);
-                let mut last_msg_time = std::time::Instant::now();
-
                 loop {
-                    match bus.timed_pop(ClockTime::from_seconds(timeout.as_secs())) {
+                    //timeout breve per accorgersi di stop_streaming, es. quando il presentatore chiude la sessione
+                    match bus.timed_pop(ClockTime::from_mseconds(BUS_POLL_INTERVAL.as_millis() as u64)) {
                         Some(msg) => {
-                            last_msg_time = std::time::Instant::now();
                             match msg.view() {
                                 gst::MessageView::Eos(..) => {
                                     println!("End of stream");
@@ -165,10 +412,7 @@ impl StreamerClient {
                             }
                         }
                         None => {
-                            if last_msg_time.elapsed() >= timeout {
-                                println!("No messages received for a while. Stream is ending.");
-                                let mut streaming = is_streaming.lock().unwrap();
-                                *streaming = false;
+                            if !*is_streaming.lock().unwrap() {
                                 break;
                             }
                         }
@@ -177,18 +421,17 @@ impl StreamerClient {
                 if let Some(pipeline) = pipeline_clone {
                     pipeline.set_state(State::Null).unwrap();
                 }
-                println!("Closing render window...");
+                println!("Closing the stream...");
                 Ok(())
 
             });
-
-            let mut streaming = self.is_streaming.lock().unwrap();
-            *streaming = true;
         }
         Ok(())
     }
 
     pub fn stop_streaming(&mut self) {
+        //ferma anche il thread del bus
+        *self.is_streaming.lock().unwrap() = false;
         if let Some(ref pipeline) = self.pipeline {
             pipeline.set_state(State::Null).unwrap();
         }
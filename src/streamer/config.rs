@@ -0,0 +1,161 @@
+use std::fmt;
+use crate::connection::protocol::DEFAULT_DISCOVERY_PORT;
+
+
+/// Speed presets accepted by x264enc, from the fastest to the slowest one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedPreset {
+    Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    Medium,
+}
+
+impl SpeedPreset {
+    pub const ALL: [SpeedPreset; 6] = [
+        SpeedPreset::Ultrafast,
+        SpeedPreset::Superfast,
+        SpeedPreset::Veryfast,
+        SpeedPreset::Faster,
+        SpeedPreset::Fast,
+        SpeedPreset::Medium,
+    ];
+
+    /// Nome del preset come lo vuole la proprietà `speed-preset` di x264enc
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpeedPreset::Ultrafast => "ultrafast",
+            SpeedPreset::Superfast => "superfast",
+            SpeedPreset::Veryfast => "veryfast",
+            SpeedPreset::Faster => "faster",
+            SpeedPreset::Fast => "fast",
+            SpeedPreset::Medium => "medium",
+        }
+    }
+}
+
+impl fmt::Display for SpeedPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Preset: {}", self.as_str())
+    }
+}
+
+
+/// Resolution of the video sent to the clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputResolution {
+    Native,
+    FullHd,
+    Hd,
+    Sd,
+}
+
+impl OutputResolution {
+    pub const ALL: [OutputResolution; 4] = [
+        OutputResolution::Native,
+        OutputResolution::FullHd,
+        OutputResolution::Hd,
+        OutputResolution::Sd,
+    ];
+
+    /// Width and height to scale to, `None` keeps the size of the captured area
+    pub fn size(&self) -> Option<(i32, i32)> {
+        match self {
+            OutputResolution::Native => None,
+            OutputResolution::FullHd => Some((1920, 1080)),
+            OutputResolution::Hd => Some((1280, 720)),
+            OutputResolution::Sd => Some((854, 480)),
+        }
+    }
+}
+
+impl fmt::Display for OutputResolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.size() {
+            Some((width, height)) => write!(f, "Resolution: {}x{}", width, height),
+            None => write!(f, "Resolution: native"),
+        }
+    }
+}
+
+
+/// Audio sources sent together with the video, with none of them only the video is streamed
+#[derive(Debug, Clone, Default)]
+pub struct AudioConfig {
+    pub system_audio: bool,
+    pub microphone: bool,
+}
+
+impl AudioConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.system_audio || self.microphone
+    }
+}
+
+
+/// Whether a viewer can ask to move the mouse and type on the presenter's desktop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RemoteControlMode {
+    #[default]
+    Disabled,
+    Enabled,
+    DryRun, // le richieste si accettano ma gli eventi non vengono eseguiti
+}
+
+
+/// Encoding profile used by `ScreenStreamer` for every pipeline it builds
+#[derive(Debug, Clone)]
+pub struct StreamConfig {
+    pub bitrate: u32,           // kbps
+    pub max_framerate: i32,
+    pub keyframe_interval: u32, // frames between two IDR
+    pub preset: SpeedPreset,
+    pub output_resolution: OutputResolution,
+    pub audio: AudioConfig,
+    pub discovery_port: u16,    // where the clients send Join
+    pub rtp_port: u16,          // source port of the video, the audio uses the next one and the RTCP the two after; 0 = any
+    pub pin: Option<String>,    // required to join the session if set
+    pub waiting_room: bool,     // the presenter admits every viewer
+    pub confidential: bool,     // never send the stream unencrypted
+    pub session_name: String,   // shown to the clients with the presenter name
+    pub presenter_name: String,
+    pub chat_enabled: bool,     // viewers can write in the chat, the presenter can change it during the session
+    pub share_pointers: bool,   // the marks of the viewers are drawn on the stream, not only shown to the presenter
+    pub remote_control: RemoteControlMode,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            bitrate: 5000,
+            max_framerate: 30,
+            keyframe_interval: 60,
+            preset: SpeedPreset::Ultrafast,
+            output_resolution: OutputResolution::Native,
+            audio: AudioConfig::default(),
+            discovery_port: DEFAULT_DISCOVERY_PORT,
+            rtp_port: 0,
+            pin: None,
+            waiting_room: false,
+            confidential: false,
+            session_name: "Screen sharing".to_string(),
+            presenter_name: crate::user_name(),
+            chat_enabled: true,
+            share_pointers: false,
+            remote_control: RemoteControlMode::Disabled,
+        }
+    }
+}
+
+impl StreamConfig {
+    /// A confidential session needs a PIN or the waiting room: otherwise anyone on the LAN
+    /// could send `Join` and receive the SRTP key
+    pub fn check_confidential(&self) -> Result<(), String> {
+        if self.confidential && self.pin.is_none() && !self.waiting_room {
+            return Err("A confidential session needs a PIN or the waiting room".to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::{mem, slice, thread};
+use crate::streamer::error::ServerError;
+use crate::streamer::xtest;
+
+
+pub const HOTKEY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+//EVIOCGBIT(EV_KEY, 96) di linux/input.h: i tasti che il dispositivo ha, KEY_MAX / 8 + 1 byte
+const EVIOCGBIT_KEY: libc::c_ulong = 0x8060_4521;
+const KEY_BITS_LEN: usize = 96;
+
+const EV_KEY: u16 = 1;
+const KEY_ESC: u16 = 1;
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_RIGHTCTRL: u16 = 97;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_RIGHTSHIFT: u16 = 54;
+
+
+/// Ctrl+Shift+Escape on the whole desktop: it revokes the remote control even while the viewer moves the mouse
+/// and the window of the application is not focused. Stops listening when dropped
+pub struct PanicHotkey {
+    stop: Arc<AtomicBool>,
+}
+
+impl PanicHotkey {
+    /// A key grab on X11, otherwise (e.g. Wayland) the keyboards are read from /dev/input, which needs read access
+    /// to the devices (usually the `input` group)
+    pub fn watch(on_panic: impl Fn() + Send + Sync + 'static) -> Result<Self, ServerError> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let on_panic = Arc::new(on_panic);
+
+        let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+        if !wayland {
+            let on_x11_panic = Arc::clone(&on_panic);
+            match xtest::grab_panic_hotkey(Arc::clone(&stop), move || on_x11_panic()) {
+                Ok(()) => return Ok(Self { stop }),
+                Err(e) => println!("The X11 panic hotkey is not available: {}", e),
+            }
+        }
+        watch_keyboards(Arc::clone(&stop), move || on_panic())?;
+        Ok(Self { stop })
+    }
+}
+
+impl Drop for PanicHotkey {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+
+//solo i dispositivi con Escape, Ctrl e Shift: mouse e altri tasti non servono
+fn is_keyboard(device: &File) -> bool {
+    let mut bits = [0u8; KEY_BITS_LEN];
+    // SAFETY: il buffer è lungo quanto dichiarato nella richiesta
+    if unsafe { libc::ioctl(device.as_raw_fd(), EVIOCGBIT_KEY, bits.as_mut_ptr()) } < 0 {
+        return false;
+    }
+    [KEY_ESC, KEY_LEFTCTRL, KEY_LEFTSHIFT]
+        .iter()
+        .all(|key| bits[*key as usize / 8] & (1 << (key % 8)) != 0)
+}
+
+fn open_keyboards() -> Result<Vec<File>, ServerError> {
+    let entries = fs::read_dir("/dev/input").map_err(|e| ServerError {
+        message: format!("evdev: cannot list /dev/input ({})", e),
+    })?;
+    let keyboards: Vec<File> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("event"))
+        .filter_map(|entry| OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(entry.path()).ok())
+        .filter(is_keyboard)
+        .collect();
+    if keyboards.is_empty() {
+        return Err(ServerError {
+            message: "evdev: no keyboard can be read from /dev/input".to_string(),
+        });
+    }
+    Ok(keyboards)
+}
+
+/// Pressed modifiers, counted on all the keyboards together
+#[derive(Debug, Default)]
+struct HotkeyState {
+    ctrl: [bool; 2],
+    shift: [bool; 2],
+}
+
+impl HotkeyState {
+    /// `value` as in evdev: 0 released, 1 pressed, 2 repeated. True when the hotkey is pressed
+    fn key(&mut self, code: u16, value: i32) -> bool {
+        let pressed = value != 0;
+        match code {
+            KEY_LEFTCTRL => self.ctrl[0] = pressed,
+            KEY_RIGHTCTRL => self.ctrl[1] = pressed,
+            KEY_LEFTSHIFT => self.shift[0] = pressed,
+            KEY_RIGHTSHIFT => self.shift[1] = pressed,
+            KEY_ESC => return value == 1 && self.ctrl.contains(&true) && self.shift.contains(&true),
+            _ => {},
+        }
+        false
+    }
+}
+
+fn watch_keyboards(stop: Arc<AtomicBool>, on_panic: impl Fn() + Send + 'static) -> Result<(), ServerError> {
+    let mut keyboards = open_keyboards()?;
+    thread::spawn(move || {
+        let mut state = HotkeyState::default();
+        while !stop.load(Ordering::Relaxed) && !keyboards.is_empty() {
+            //una tastiera scollegata dà errore e si smette di leggerla
+            keyboards.retain_mut(|keyboard| loop {
+                // SAFETY: input_event è una struct C di soli interi, tutta a zero è valida
+                let mut event: libc::input_event = unsafe { mem::zeroed() };
+                // SAFETY: il buffer è la struct stessa, il kernel scrive eventi interi
+                let bytes = unsafe {
+                    slice::from_raw_parts_mut(&mut event as *mut _ as *mut u8, mem::size_of::<libc::input_event>())
+                };
+                match keyboard.read(bytes) {
+                    Ok(read) if read == bytes.len() => {
+                        if event.type_ == EV_KEY && state.key(event.code, event.value) {
+                            on_panic();
+                        }
+                    },
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                    _ => return false,
+                }
+            });
+            thread::sleep(HOTKEY_POLL_INTERVAL);
+        }
+        if !stop.load(Ordering::Relaxed) {
+            println!("Panic hotkey stopped: no keyboard left in /dev/input");
+        }
+    });
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_needs_ctrl_and_shift() {
+        let mut state = HotkeyState::default();
+        assert!(!state.key(KEY_ESC, 1));
+        assert!(!state.key(KEY_RIGHTCTRL, 1));
+        assert!(!state.key(KEY_ESC, 1));
+        assert!(!state.key(KEY_LEFTSHIFT, 1));
+        assert!(!state.key(KEY_ESC, 2));
+        assert!(state.key(KEY_ESC, 1));
+        assert!(!state.key(KEY_ESC, 0));
+        assert!(!state.key(KEY_LEFTSHIFT, 0));
+        assert!(!state.key(KEY_ESC, 1));
+    }
+}
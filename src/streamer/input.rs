@@ -0,0 +1,462 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use crate::streamer::error::ServerError;
+use crate::connection::protocol::InputEvent;
+
+
+/// Moves the mouse and presses the keys of the presenter's desktop on behalf of the viewer in control.
+///
+/// Coordinates are absolute pixels of the desktop (all the monitors together),
+/// `ScreenStreamer` converts the ones of the viewer through the capture region and the monitor position.
+pub trait InputInjector: Send {
+    fn name(&self) -> String;
+    fn move_to(&mut self, x: i32, y: i32) -> Result<(), ServerError>;
+    /// `button` as in X11: 1 left, 2 middle, 3 right
+    fn button(&mut self, button: u8, pressed: bool) -> Result<(), ServerError>;
+    /// Wheel steps, positive `dy` scrolls down and positive `dx` right
+    fn scroll(&mut self, dx: i32, dy: i32) -> Result<(), ServerError>;
+    fn key(&mut self, key: &KeyCode, pressed: bool) -> Result<(), ServerError>;
+}
+
+
+/// A key in the two forms the injectors need
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCode {
+    pub keysym: u32, // X11, per XTest
+    pub evdev: u16,  // kernel, per uinput
+}
+
+//codici evdev delle lettere in ordine alfabetico (KEY_A, KEY_B, ...)
+const LETTERS: [u16; 26] = [30, 48, 46, 32, 18, 33, 34, 35, 23, 36, 37, 38, 50, 49, 24, 25, 16, 19, 31, 20, 22, 47, 17, 45, 21, 44];
+
+const NAMED_KEYS: [(&str, u32, u16); 46] = [
+    ("Return", 0xff0d, 28),
+    ("BackSpace", 0xff08, 14),
+    ("Tab", 0xff09, 15),
+    ("Escape", 0xff1b, 1),
+    ("space", 0x20, 57),
+    ("Delete", 0xffff, 111),
+    ("Insert", 0xff63, 110),
+    ("Home", 0xff50, 102),
+    ("End", 0xff57, 107),
+    ("Page_Up", 0xff55, 104),
+    ("Page_Down", 0xff56, 109),
+    ("Left", 0xff51, 105),
+    ("Up", 0xff52, 103),
+    ("Right", 0xff53, 106),
+    ("Down", 0xff54, 108),
+    ("Shift_L", 0xffe1, 42),
+    ("Shift_R", 0xffe2, 54),
+    ("Control_L", 0xffe3, 29),
+    ("Control_R", 0xffe4, 97),
+    ("Alt_L", 0xffe9, 56),
+    ("Alt_R", 0xffea, 100),
+    ("Super_L", 0xffeb, 125),
+    ("Caps_Lock", 0xffe5, 58),
+    ("F1", 0xffbe, 59),
+    ("F2", 0xffbf, 60),
+    ("F3", 0xffc0, 61),
+    ("F4", 0xffc1, 62),
+    ("F5", 0xffc2, 63),
+    ("F6", 0xffc3, 64),
+    ("F7", 0xffc4, 65),
+    ("F8", 0xffc5, 66),
+    ("F9", 0xffc6, 67),
+    ("F10", 0xffc7, 68),
+    ("F11", 0xffc8, 87),
+    ("F12", 0xffc9, 88),
+    ("minus", 0x2d, 12),
+    ("equal", 0x3d, 13),
+    ("bracketleft", 0x5b, 26),
+    ("bracketright", 0x5d, 27),
+    ("semicolon", 0x3b, 39),
+    ("apostrophe", 0x27, 40),
+    ("grave", 0x60, 41),
+    ("backslash", 0x5c, 43),
+    ("comma", 0x2c, 51),
+    ("period", 0x2e, 52),
+    ("slash", 0x2f, 53),
+];
+
+impl KeyCode {
+    /// Looks up an X keysym name, the ones GStreamer puts in the navigation events.
+    /// Shifted characters are sent as the key plus Shift, so "A" is the same key as "a"
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            if c.is_ascii_alphabetic() {
+                let c = c.to_ascii_lowercase();
+                return Some(Self { keysym: c as u32, evdev: LETTERS[(c as u8 - b'a') as usize] });
+            }
+            if let Some(digit) = c.to_digit(10) {
+                //KEY_1 = 2 ... KEY_9 = 10, KEY_0 = 11
+                let evdev = if digit == 0 { 11 } else { digit as u16 + 1 };
+                return Some(Self { keysym: c as u32, evdev });
+            }
+        }
+        NAMED_KEYS.iter()
+            .find(|(known, _, _)| *known == name)
+            .map(|(_, keysym, evdev)| Self { keysym: *keysym, evdev: *evdev })
+    }
+
+    /// Every key of the table, uinput has to declare them when the device is created
+    pub fn all() -> Vec<Self> {
+        let letters = (b'a'..=b'z').filter_map(|c| Self::from_name(&(c as char).to_string()));
+        let digits = (b'0'..=b'9').filter_map(|c| Self::from_name(&(c as char).to_string()));
+        let named = NAMED_KEYS.iter().map(|(_, keysym, evdev)| Self { keysym: *keysym, evdev: *evdev });
+        letters.chain(digits).chain(named).collect()
+    }
+}
+
+
+//un mouse a 60 Hz per tutta la sessione non deve riempire la memoria
+const MAX_RECORDED_EVENTS: usize = 1000;
+
+
+/// What `RecordingInjector` received, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InjectedEvent {
+    MoveTo(i32, i32),
+    Button(u8, bool),
+    Scroll(i32, i32),
+    Key(KeyCode, bool),
+}
+
+/// Keeps the last events instead of injecting them: to try the remote control without touching the desktop
+/// (`--remote-control-dry-run`) and to check the conversion of the coordinates.
+/// The clones share the same events, so a test can keep one and give the other to `RemoteControl`
+#[derive(Debug, Clone)]
+pub struct RecordingInjector {
+    events: Arc<Mutex<VecDeque<InjectedEvent>>>,
+    capacity: usize,
+}
+
+impl Default for RecordingInjector {
+    fn default() -> Self {
+        Self::with_capacity(MAX_RECORDED_EVENTS)
+    }
+}
+
+impl RecordingInjector {
+    /// Keeps at most `capacity` events, the oldest are dropped
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// The recorded events, oldest first
+    #[cfg(test)]
+    pub fn events(&self) -> Vec<InjectedEvent> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn record(&self, event: InjectedEvent) -> Result<(), ServerError> {
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+        if self.capacity > 0 {
+            events.push_back(event);
+        }
+        Ok(())
+    }
+}
+
+impl InputInjector for RecordingInjector {
+    fn name(&self) -> String {
+        "dry run".to_string()
+    }
+
+    fn move_to(&mut self, x: i32, y: i32) -> Result<(), ServerError> {
+        self.record(InjectedEvent::MoveTo(x, y))
+    }
+
+    fn button(&mut self, button: u8, pressed: bool) -> Result<(), ServerError> {
+        self.record(InjectedEvent::Button(button, pressed))
+    }
+
+    fn scroll(&mut self, dx: i32, dy: i32) -> Result<(), ServerError> {
+        self.record(InjectedEvent::Scroll(dx, dy))
+    }
+
+    fn key(&mut self, key: &KeyCode, pressed: bool) -> Result<(), ServerError> {
+        self.record(InjectedEvent::Key(*key, pressed))
+    }
+}
+
+
+/// Injector of the desktop: XTest on X11, uinput otherwise (e.g. Wayland, needs write access to /dev/uinput)
+#[cfg(target_os = "linux")]
+pub fn system_injector() -> Result<Box<dyn InputInjector>, ServerError> {
+    use crate::streamer::{uinput::UinputInjector, xtest::XTestInjector};
+
+    let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    if !wayland {
+        match XTestInjector::connect() {
+            Ok(injector) => return Ok(Box::new(injector)),
+            Err(e) => println!("XTest is not available: {}", e),
+        }
+    }
+    //uinput è un dispositivo assoluto che copre tutto il desktop
+    let (width, height) = desktop_size();
+    Ok(Box::new(UinputInjector::create(width, height)?))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn system_injector() -> Result<Box<dyn InputInjector>, ServerError> {
+    Err(ServerError {
+        message: "Remote control is only supported on linux".to_string(),
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn desktop_size() -> (i32, i32) {
+    screenshots::Screen::all()
+        .unwrap_or_default()
+        .iter()
+        .fold((0, 0), |(width, height), screen| {
+            let info = &screen.display_info;
+            (width.max(info.x + info.width as i32), height.max(info.y + info.height as i32))
+        })
+}
+
+
+//una rotella vera non fa più di qualche scatto per evento, il resto arriva con i messaggi successivi
+const MAX_SCROLL_STEPS: f64 = 10.0;
+
+fn scroll_steps(delta: f64) -> i32 {
+    delta.round().clamp(-MAX_SCROLL_STEPS, MAX_SCROLL_STEPS) as i32
+}
+
+
+/// The injector while a viewer is in control, with what it keeps pressed:
+/// when the control is revoked nothing must stay held down on the presenter's desktop
+pub struct RemoteControl {
+    injector: Box<dyn InputInjector>,
+    pressed_keys: Vec<KeyCode>,
+    pressed_buttons: Vec<u8>,
+}
+
+impl RemoteControl {
+    pub fn new(injector: Box<dyn InputInjector>) -> Self {
+        println!("Remote control through {}", injector.name());
+        Self {
+            injector,
+            pressed_keys: Vec::new(),
+            pressed_buttons: Vec::new(),
+        }
+    }
+
+    /// `to_desktop` converts the coordinates of the viewer (0-1 across the stream) into pixels of the desktop,
+    /// events outside of the shared area are dropped. Scrolls are limited to `MAX_SCROLL_STEPS` steps
+    pub fn apply(&mut self, event: &InputEvent, to_desktop: impl Fn(f64, f64) -> Option<(i32, i32)>) -> Result<(), ServerError> {
+        let values = match event {
+            InputEvent::MouseMove { x, y } | InputEvent::MouseButton { x, y, .. } => vec![*x, *y],
+            InputEvent::Scroll { x, y, dx, dy } => vec![*x, *y, *dx, *dy],
+            InputEvent::Key { .. } => Vec::new(),
+        };
+        //arrivano dalla rete: NaN o infinito non devono arrivare agli injector
+        if values.iter().any(|value| !value.is_finite()) {
+            return Err(ServerError {
+                message: "Remote control: invalid coordinates".to_string(),
+            });
+        }
+        match event {
+            InputEvent::MouseMove { x, y } => {
+                if let Some((x, y)) = to_desktop(*x, *y) {
+                    self.injector.move_to(x, y)?;
+                }
+            },
+            InputEvent::MouseButton { x, y, button, pressed } => {
+                //i click si fanno dove li ha fatti lo spettatore, anche se il movimento è andato perso
+                if let Some((x, y)) = to_desktop(*x, *y) {
+                    self.injector.move_to(x, y)?;
+                } else if *pressed {
+                    return Ok(());
+                }
+                self.injector.button(*button, *pressed)?;
+                self.pressed_buttons.retain(|held| held != button);
+                if *pressed {
+                    self.pressed_buttons.push(*button);
+                }
+            },
+            InputEvent::Scroll { x, y, dx, dy } => {
+                if let Some((x, y)) = to_desktop(*x, *y) {
+                    self.injector.move_to(x, y)?;
+                    self.injector.scroll(scroll_steps(*dx), scroll_steps(*dy))?;
+                }
+            },
+            InputEvent::Key { key, pressed } => {
+                let Some(code) = KeyCode::from_name(key) else {
+                    return Err(ServerError {
+                        message: format!("Remote control: unknown key '{}'", key),
+                    });
+                };
+                self.injector.key(&code, *pressed)?;
+                self.pressed_keys.retain(|held| *held != code);
+                if *pressed {
+                    self.pressed_keys.push(code);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Releases every key and button the viewer is holding
+    pub fn release_all(&mut self) {
+        for key in std::mem::take(&mut self.pressed_keys) {
+            if let Err(e) = self.injector.key(&key, false) {
+                println!("Failed to release a key: {}", e);
+            }
+        }
+        for button in std::mem::take(&mut self.pressed_buttons) {
+            if let Err(e) = self.injector.button(button, false) {
+                println!("Failed to release a mouse button: {}", e);
+            }
+        }
+    }
+}
+
+impl Drop for RemoteControl {
+    fn drop(&mut self) {
+        self.release_all();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str, pressed: bool) -> InputEvent {
+        InputEvent::Key { key: name.to_string(), pressed }
+    }
+
+    fn click(button: u8, pressed: bool) -> InputEvent {
+        InputEvent::MouseButton { x: 0.5, y: 0.5, button, pressed }
+    }
+
+    fn to_desktop(x: f64, y: f64) -> Option<(i32, i32)> {
+        Some(((x * 100.0) as i32, (y * 100.0) as i32))
+    }
+
+    fn recording() -> (RecordingInjector, RemoteControl) {
+        let recorder = RecordingInjector::default();
+        let remote = RemoteControl::new(Box::new(recorder.clone()));
+        (recorder, remote)
+    }
+
+    #[test]
+    fn released_keys_and_buttons_are_not_released_again() {
+        let (recorder, mut remote) = recording();
+        remote.apply(&key("a", true), to_desktop).unwrap();
+        remote.apply(&key("a", false), to_desktop).unwrap();
+        remote.apply(&click(1, true), to_desktop).unwrap();
+        remote.apply(&click(1, false), to_desktop).unwrap();
+        remote.release_all();
+
+        let a = KeyCode::from_name("a").unwrap();
+        assert_eq!(recorder.events(), vec![
+            InjectedEvent::Key(a, true),
+            InjectedEvent::Key(a, false),
+            InjectedEvent::MoveTo(50, 50),
+            InjectedEvent::Button(1, true),
+            InjectedEvent::MoveTo(50, 50),
+            InjectedEvent::Button(1, false),
+        ]);
+    }
+
+    #[test]
+    fn release_all_releases_what_is_held() {
+        let (recorder, mut remote) = recording();
+        remote.apply(&key("Shift_L", true), to_desktop).unwrap();
+        remote.apply(&key("A", true), to_desktop).unwrap();
+        remote.apply(&click(3, true), to_desktop).unwrap();
+        remote.release_all();
+
+        let events = recorder.events();
+        let released = &events[events.len() - 3..];
+        assert_eq!(released, [
+            InjectedEvent::Key(KeyCode::from_name("Shift_L").unwrap(), false),
+            InjectedEvent::Key(KeyCode::from_name("a").unwrap(), false),
+            InjectedEvent::Button(3, false),
+        ]);
+
+        // niente resta premuto, una seconda volta non rilascia nulla
+        remote.release_all();
+        assert_eq!(recorder.events().len(), events.len());
+    }
+
+    #[test]
+    fn drop_releases_what_is_held() {
+        let (recorder, mut remote) = recording();
+        remote.apply(&key("Control_L", true), to_desktop).unwrap();
+        remote.apply(&click(1, true), to_desktop).unwrap();
+        drop(remote);
+
+        let events = recorder.events();
+        assert_eq!(&events[events.len() - 2..], [
+            InjectedEvent::Key(KeyCode::from_name("Control_L").unwrap(), false),
+            InjectedEvent::Button(1, false),
+        ]);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let (recorder, mut remote) = recording();
+        assert!(remote.apply(&key("NotAKey", true), to_desktop).is_err());
+        remote.release_all();
+        assert!(recorder.events().is_empty());
+    }
+
+    #[test]
+    fn presses_outside_the_shared_area_are_dropped_but_releases_are_not() {
+        let (recorder, mut remote) = recording();
+        remote.apply(&click(1, true), to_desktop).unwrap();
+        remote.apply(&click(1, false), |_, _| None).unwrap();
+        remote.apply(&click(2, true), |_, _| None).unwrap();
+        remote.release_all();
+
+        assert_eq!(recorder.events(), vec![
+            InjectedEvent::MoveTo(50, 50),
+            InjectedEvent::Button(1, true),
+            InjectedEvent::Button(1, false),
+        ]);
+    }
+
+    #[test]
+    fn huge_scrolls_are_limited() {
+        let (recorder, mut remote) = recording();
+        remote.apply(&InputEvent::Scroll { x: 0.5, y: 0.5, dx: -1e18, dy: 1e18 }, to_desktop).unwrap();
+        remote.apply(&InputEvent::Scroll { x: 0.5, y: 0.5, dx: 0.0, dy: -2.4 }, to_desktop).unwrap();
+
+        assert_eq!(recorder.events(), vec![
+            InjectedEvent::MoveTo(50, 50),
+            InjectedEvent::Scroll(-10, 10),
+            InjectedEvent::MoveTo(50, 50),
+            InjectedEvent::Scroll(0, -2),
+        ]);
+    }
+
+    #[test]
+    fn non_finite_values_are_rejected() {
+        let (recorder, mut remote) = recording();
+        assert!(remote.apply(&InputEvent::Scroll { x: 0.5, y: 0.5, dx: 0.0, dy: f64::NEG_INFINITY }, to_desktop).is_err());
+        assert!(remote.apply(&InputEvent::Scroll { x: 0.5, y: 0.5, dx: f64::NAN, dy: 1.0 }, to_desktop).is_err());
+        assert!(remote.apply(&InputEvent::MouseMove { x: f64::INFINITY, y: 0.5 }, to_desktop).is_err());
+        assert!(remote.apply(&InputEvent::MouseButton { x: f64::NAN, y: 0.5, button: 1, pressed: true }, to_desktop).is_err());
+        assert!(recorder.events().is_empty());
+    }
+
+    #[test]
+    fn recorder_keeps_only_the_last_events() {
+        let recorder = RecordingInjector::with_capacity(2);
+        let mut injector = recorder.clone();
+        for x in 0..5 {
+            injector.move_to(x, 0).unwrap();
+        }
+        assert_eq!(recorder.events(), vec![InjectedEvent::MoveTo(3, 0), InjectedEvent::MoveTo(4, 0)]);
+    }
+}
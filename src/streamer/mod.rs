@@ -1,3 +1,16 @@
 pub mod streamer;
 pub mod client;
-pub mod error;
\ No newline at end of file
+pub mod error;
+pub mod config;
+pub mod capture;
+pub mod audio;
+pub mod placeholder;
+pub mod srtp;
+pub mod pointer;
+pub mod input;
+#[cfg(target_os = "linux")]
+pub mod xtest;
+#[cfg(target_os = "linux")]
+pub mod uinput;
+#[cfg(target_os = "linux")]
+pub mod hotkey;
\ No newline at end of file
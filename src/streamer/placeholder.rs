@@ -0,0 +1,334 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use gst::{Element, Pipeline, prelude::*};
+use crate::streamer::error::ServerError;
+
+
+//Le immagini sono incluse nell'eseguibile, così non serve lanciarlo dalla cartella del progetto
+const BLANK_IMAGE: &[u8] = include_bytes!("../images/blank.png");
+const END_STREAM_IMAGE: &[u8] = include_bytes!("../images/end_stream_ai.png");
+
+const RIGHT_BACK_TEXT: &str = "Presenter will be right back";
+const PAUSED_TEXT: &str = "Paused";
+const BACKGROUND_COLOR: u32 = 0xff202020; // ARGB
+
+//Durante la pausa basta produrre pochi frame al secondo, videorate li ripete per l'encoder
+const PAUSED_FRAMERATE: i32 = 2;
+
+
+/// What the viewers see instead of the captured screen
+#[derive(Debug, Clone, PartialEq)]
+pub enum Placeholder {
+    Image(Vec<u8>),  // content of an image file, in any format decodebin understands
+    Color(u32),      // ARGB
+    Text(String),
+}
+
+impl Placeholder {
+    pub fn blank() -> Self {
+        Placeholder::Image(BLANK_IMAGE.to_vec())
+    }
+
+    pub fn end_of_stream() -> Self {
+        Placeholder::Image(END_STREAM_IMAGE.to_vec())
+    }
+
+    pub fn right_back() -> Self {
+        Placeholder::Text(RIGHT_BACK_TEXT.to_string())
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, io::Error> {
+        Ok(Placeholder::Image(std::fs::read(path)?))
+    }
+}
+
+
+/// Elements of the placeholder branch currently linked to the input-selector
+pub struct PlaceholderBranch {
+    pub elements: Vec<Element>,
+    pub pad: gst::Pad,
+}
+
+impl PlaceholderBranch {
+
+    /// Builds the branch that produces `placeholder` and links it to a new pad of `selector`.
+    /// `output_caps` are the caps of the live branch, so switching doesn't change the resolution
+    pub fn build(pipeline: &Pipeline, selector: &Element, placeholder: &Placeholder, output_caps: Option<gst::Caps>) -> Result<Self, ServerError> {
+        let mut elements = Vec::new();
+
+        let tail = match placeholder {
+            Placeholder::Image(bytes) => Self::image_source(pipeline, bytes, &mut elements)?,
+            Placeholder::Color(argb) => {
+                let videotestsrc = Self::solid_color(*argb)?;
+                Self::add(pipeline, &videotestsrc, &mut elements)?;
+                videotestsrc
+            },
+            Placeholder::Text(text) => {
+                let videotestsrc = Self::solid_color(BACKGROUND_COLOR)?;
+                let textoverlay = Self::text_overlay(text)?;
+                Self::add(pipeline, &videotestsrc, &mut elements)?;
+                Self::add(pipeline, &textoverlay, &mut elements)?;
+                videotestsrc.link(&textoverlay).map_err(|_| ServerError {
+                    message: "Failed to link textoverlay".to_string(),
+                })?;
+                textoverlay
+            },
+        };
+
+        let pad = Self::link_to_selector(pipeline, selector, &tail, "placeholder_caps", output_caps, &mut elements)?;
+        Ok(Self { elements, pad })
+    }
+
+    /// Builds the branch shown while the stream is paused: `last_frame` dimmed, with "Paused" over it.
+    /// Without a frame (nothing was sent yet) only the text is shown.
+    /// The frame is drawn at a low framerate and videorate repeats it at the live one: the input-selector,
+    /// and so the encoder, keep the same caps, and the repeated frames cost almost nothing to encode
+    pub fn paused(pipeline: &Pipeline, selector: &Element, last_frame: Option<gst::Sample>, output_caps: Option<gst::Caps>) -> Result<Self, ServerError> {
+        let mut elements = Vec::new();
+
+        let output_caps = last_frame.as_ref()
+            .and_then(|sample| sample.caps().map(|caps| caps.to_owned()))
+            .or(output_caps);
+
+        let background = match last_frame {
+            Some(sample) => {
+                let appsrc = gst::ElementFactory::make("appsrc").build()
+                    .map_err(|_| ServerError {
+                        message: "Failed to create appsrc for the paused frame".to_string(),
+                    })?;
+                let imagefreeze = gst::ElementFactory::make("imagefreeze")
+                    .property("is-live", true)
+                    .build()
+                    .map_err(|_| ServerError {
+                        message: "Failed to create imagefreeze".to_string(),
+                    })?;
+                let videoconvert = gst::ElementFactory::make("videoconvert").build()
+                    .map_err(|_| ServerError {
+                        message: "Failed to create videoconvert for the paused frame".to_string(),
+                    })?;
+                let videobalance = gst::ElementFactory::make("videobalance")
+                    .property("brightness", -0.4f64)
+                    .property("saturation", 0.5f64)
+                    .build()
+                    .map_err(|_| ServerError {
+                        message: "Failed to create videobalance".to_string(),
+                    })?;
+
+                let low_rate = Self::low_rate()?;
+
+                for element in [&appsrc, &imagefreeze, &low_rate, &videoconvert, &videobalance] {
+                    Self::add(pipeline, element, &mut elements)?;
+                }
+                gst::Element::link_many(&[&appsrc, &imagefreeze, &low_rate, &videoconvert, &videobalance]).map_err(|_| ServerError {
+                    message: "Failed to link the paused frame elements".to_string(),
+                })?;
+
+                //push-sample imposta anche le caps di appsrc
+                let _: gst::FlowReturn = appsrc.emit_by_name("push-sample", &[&sample]);
+                let _: gst::FlowReturn = appsrc.emit_by_name("end-of-stream", &[]);
+                videobalance
+            },
+            None => {
+                let videotestsrc = Self::solid_color(BACKGROUND_COLOR)?;
+                let low_rate = Self::low_rate()?;
+                Self::add(pipeline, &videotestsrc, &mut elements)?;
+                Self::add(pipeline, &low_rate, &mut elements)?;
+                videotestsrc.link(&low_rate).map_err(|_| ServerError {
+                    message: "Failed to link the paused background".to_string(),
+                })?;
+                low_rate
+            },
+        };
+
+        let textoverlay = Self::text_overlay(PAUSED_TEXT)?;
+        let videorate = gst::ElementFactory::make("videorate").build()
+            .map_err(|_| ServerError {
+                message: "Failed to create videorate for the paused frame".to_string(),
+            })?;
+        Self::add(pipeline, &textoverlay, &mut elements)?;
+        Self::add(pipeline, &videorate, &mut elements)?;
+        gst::Element::link_many(&[&background, &textoverlay, &videorate]).map_err(|_| ServerError {
+            message: "Failed to link textoverlay".to_string(),
+        })?;
+
+        let pad = Self::link_to_selector(pipeline, selector, &videorate, "paused_caps", output_caps, &mut elements)?;
+        Ok(Self { elements, pad })
+    }
+
+    /// `tail ! videoconvert ! videoscale ! capsfilter`, linked to a new pad of the input-selector
+    fn link_to_selector(pipeline: &Pipeline, selector: &Element, tail: &Element, caps_name: &str, output_caps: Option<gst::Caps>, elements: &mut Vec<Element>) -> Result<gst::Pad, ServerError> {
+        let videoconvert = gst::ElementFactory::make("videoconvert").build()
+            .map_err(|_| ServerError {
+                message: "Failed to create videoconvert for the placeholder".to_string(),
+            })?;
+
+        let videoscale = gst::ElementFactory::make("videoscale")
+            .property("add-borders", true)
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create videoscale for the placeholder".to_string(),
+            })?;
+
+        let capsfilter = gst::ElementFactory::make("capsfilter")
+            .name(caps_name)
+            .property("caps", output_caps.unwrap_or_else(|| gst::Caps::new_empty_simple("video/x-raw")))
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create capsfilter for the placeholder".to_string(),
+            })?;
+
+        for element in [&videoconvert, &videoscale, &capsfilter] {
+            Self::add(pipeline, element, elements)?;
+        }
+        gst::Element::link_many(&[tail, &videoconvert, &videoscale, &capsfilter]).map_err(|_| ServerError {
+            message: "Failed to link the placeholder elements".to_string(),
+        })?;
+
+        let pad = selector.request_pad_simple("sink_%u").ok_or(ServerError {
+            message: "Failed to request a pad from input-selector".to_string(),
+        })?;
+        capsfilter.static_pad("src").unwrap().link(&pad).map_err(|_| ServerError {
+            message: "Failed to link the placeholder to input-selector".to_string(),
+        })?;
+
+        Ok(pad)
+    }
+
+    /// Removes the branch from a running pipeline, releasing its selector pad
+    pub fn remove(self, pipeline: &Pipeline, selector: &Element) {
+        for element in &self.elements {
+            let _ = element.set_state(gst::State::Null);
+        }
+        let _ = pipeline.remove_many(&self.elements);
+        selector.release_request_pad(&self.pad);
+    }
+
+    fn add(pipeline: &Pipeline, element: &Element, elements: &mut Vec<Element>) -> Result<(), ServerError> {
+        pipeline.add(element).map_err(|_| ServerError {
+            message: "Failed to add placeholder element to pipeline".to_string(),
+        })?;
+        elements.push(element.clone());
+        Ok(())
+    }
+
+    /// Makes imagefreeze or videotestsrc before it produce only `PAUSED_FRAMERATE` frames per second
+    fn low_rate() -> Result<Element, ServerError> {
+        gst::ElementFactory::make("capsfilter")
+            .property("caps", gst::Caps::builder("video/x-raw")
+                .field("framerate", gst::Fraction::new(PAUSED_FRAMERATE, 1))
+                .build())
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create capsfilter for the paused frame".to_string(),
+            })
+    }
+
+    fn text_overlay(text: &str) -> Result<Element, ServerError> {
+        gst::ElementFactory::make("textoverlay")
+            .property("text", text)
+            .property("font-desc", "Sans 36")
+            .property_from_str("valignment", "center")
+            .property_from_str("halignment", "center")
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create textoverlay".to_string(),
+            })
+    }
+
+    fn solid_color(argb: u32) -> Result<Element, ServerError> {
+        gst::ElementFactory::make("videotestsrc")
+            .property("is-live", true)
+            .property_from_str("pattern", "solid-color")
+            .property("foreground-color", argb)
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create videotestsrc for the placeholder".to_string(),
+            })
+    }
+
+    /// `appsrc ! decodebin ! videoconvert ! imagefreeze`: the image is pushed once and repeated
+    fn image_source(pipeline: &Pipeline, bytes: &[u8], elements: &mut Vec<Element>) -> Result<Element, ServerError> {
+        let appsrc = gst::ElementFactory::make("appsrc").build()
+            .map_err(|_| ServerError {
+                message: "Failed to create appsrc".to_string(),
+            })?;
+
+        let decodebin = gst::ElementFactory::make("decodebin").build()
+            .map_err(|_| ServerError {
+                message: "Failed to create decodebin for the placeholder".to_string(),
+            })?;
+
+        let videoconvert = gst::ElementFactory::make("videoconvert").build()
+            .map_err(|_| ServerError {
+                message: "Failed to create videoconvert for the placeholder image".to_string(),
+            })?;
+
+        let imagefreeze = gst::ElementFactory::make("imagefreeze")
+            .property("is-live", true)
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create imagefreeze".to_string(),
+            })?;
+
+        for element in [&appsrc, &decodebin, &videoconvert, &imagefreeze] {
+            Self::add(pipeline, element, elements)?;
+        }
+
+        appsrc.link(&decodebin).map_err(|_| ServerError {
+            message: "Failed to link appsrc to decodebin".to_string(),
+        })?;
+        videoconvert.link(&imagefreeze).map_err(|_| ServerError {
+            message: "Failed to link videoconvert to imagefreeze".to_string(),
+        })?;
+
+        let videoconvert_weak = videoconvert.downgrade();
+        decodebin.connect_pad_added(move |_, src_pad| {
+            let Some(videoconvert) = videoconvert_weak.upgrade() else {
+                return;
+            };
+            let sink_pad = videoconvert.static_pad("sink").unwrap();
+            if !sink_pad.is_linked() {
+                if let Err(e) = src_pad.link(&sink_pad) {
+                    println!("Failed to link the decoded placeholder image: {:?}", e);
+                }
+            }
+        });
+
+        //L'immagine viene accodata subito: appsrc la consegna appena la pipeline parte
+        let _: gst::FlowReturn = appsrc.emit_by_name("push-buffer", &[&gst::Buffer::from_slice(bytes.to_vec())]);
+        let _: gst::FlowReturn = appsrc.emit_by_name("end-of-stream", &[]);
+
+        Ok(imagefreeze)
+    }
+}
+
+
+/// Kind of placeholder chosen on the streamer screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaceholderKind {
+    BlankImage,
+    SolidColor,
+    RightBack,
+    CustomImage,
+}
+
+impl PlaceholderKind {
+    pub const ALL: [PlaceholderKind; 4] = [
+        PlaceholderKind::BlankImage,
+        PlaceholderKind::SolidColor,
+        PlaceholderKind::RightBack,
+        PlaceholderKind::CustomImage,
+    ];
+}
+
+impl fmt::Display for PlaceholderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlaceholderKind::BlankImage => write!(f, "Blank with: default image"),
+            PlaceholderKind::SolidColor => write!(f, "Blank with: black screen"),
+            PlaceholderKind::RightBack => write!(f, "Blank with: \"{}\"", RIGHT_BACK_TEXT),
+            PlaceholderKind::CustomImage => write!(f, "Blank with: your image"),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use gst::{Element, prelude::*};
+use crate::streamer::error::ServerError;
+
+
+/// How long a marker stays visible after the click of the viewer
+pub const POINTER_DURATION: Duration = Duration::from_secs(3);
+
+//Ogni spettatore ha sempre lo stesso colore, scelto in base al suo indirizzo
+const COLORS: [(u8, u8, u8); 6] = [
+    (230, 57, 70),
+    (42, 157, 143),
+    (244, 162, 97),
+    (69, 123, 157),
+    (155, 93, 229),
+    (233, 196, 106),
+];
+
+
+/// Point clicked by a viewer on the received video, `x` and `y` go from 0 to 1 across the stream
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointerMark {
+    pub viewer: SocketAddr,
+    pub name: String, // nickname o indirizzo dello spettatore
+    pub x: f64,
+    pub y: f64,
+    pub at: Instant,
+}
+
+impl PointerMark {
+    pub fn is_expired(&self) -> bool {
+        self.at.elapsed() > POINTER_DURATION
+    }
+
+    pub fn color(&self) -> (u8, u8, u8) {
+        let seed = match self.viewer.ip() {
+            std::net::IpAddr::V4(ip) => ip.octets().iter().map(|byte| *byte as usize).sum::<usize>(),
+            std::net::IpAddr::V6(ip) => ip.octets().iter().map(|byte| *byte as usize).sum::<usize>(),
+        };
+        COLORS[(seed + self.viewer.port() as usize) % COLORS.len()]
+    }
+
+    // textoverlay vuole il colore come ARGB
+    fn argb(&self) -> u32 {
+        let (red, green, blue) = self.color();
+        0xff00_0000 | (red as u32) << 16 | (green as u32) << 8 | blue as u32
+    }
+}
+
+
+/// True if textoverlay (pango, gst-plugins-base) is installed and the markers can be drawn on the stream
+pub fn is_available() -> bool {
+    gst::ElementFactory::find("textoverlay").is_some()
+}
+
+
+/// Gap in pixels between the dot and the name of the viewer, the font of the overlays has a fixed size in pixels too
+const LABEL_GAP: i32 = 26;
+
+/// The two overlays that draw the last marker on the video sent to every viewer, the position is set by `show`.
+/// With `position` textoverlay centers its whole box on xpos/ypos: the dot is drawn alone so it falls exactly on
+/// the click, the name is a second overlay centered just below it
+pub fn overlays() -> Result<(Element, Element), ServerError> {
+    let dot = text_overlay("pointeroverlay", "Sans Bold 18")?;
+    let label = text_overlay("pointerlabel", "Sans Bold 14")?;
+    Ok((dot, label))
+}
+
+fn text_overlay(name: &str, font: &str) -> Result<Element, ServerError> {
+    gst::ElementFactory::make("textoverlay")
+        .name(name)
+        .property("text", "")
+        .property_from_str("halignment", "position")
+        .property_from_str("valignment", "position")
+        .property("font-desc", font)
+        .property("shaded-background", false)
+        .property("draw-outline", true)
+        .build()
+        .map_err(|_| ServerError {
+            message: "Failed to create textoverlay for the pointers".to_string(),
+        })
+}
+
+
+/// Moves the marker of the overlays on `mark`, with `None` nothing is drawn
+pub fn show(dot: &Element, label: &Element, mark: Option<&PointerMark>) {
+    match mark {
+        Some(mark) => {
+            //xpos e ypos sono relativi al frame, come le coordinate mandate dal client
+            for overlay in [dot, label] {
+                overlay.set_property("color", mark.argb());
+                overlay.set_property("xpos", mark.x);
+                overlay.set_property("ypos", mark.y);
+            }
+            dot.set_property("text", "●");
+            //vicino al bordo in basso il nome va sopra il punto
+            let gap = match mark.y > 0.9 {
+                true => -LABEL_GAP,
+                false => LABEL_GAP,
+            };
+            label.set_property("deltay", gap);
+            label.set_property("text", mark.name.as_str());
+        },
+        None => {
+            dot.set_property("text", "");
+            label.set_property("text", "");
+        },
+    }
+}
@@ -1,9 +1,94 @@
-use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use gst::{Pipeline, State, prelude::*};
-use cfg_if::cfg_if;
 use crate::streamer::error::ServerError;
+use crate::streamer::capture::CaptureSource;
+use crate::streamer::audio::{self, MICROPHONE_VOLUME, SYSTEM_VOLUME};
+use crate::streamer::config::{RemoteControlMode, StreamConfig};
+use crate::streamer::placeholder::{Placeholder, PlaceholderBranch};
+use crate::streamer::srtp::{self, SrtpKey};
+use crate::streamer::pointer::{self, PointerMark};
+use crate::streamer::input::{self, RecordingInjector, RemoteControl};
+use crate::connection::protocol::InputEvent;
+use crate::connection::registry::ClientRegistry;
 
 
+//Pad dell'input-selector collegato alla cattura, il primo richiesto in create_pipeline2
+const LIVE_PAD: &str = "sink_0";
+
+pub const VIDEO_CODEC: &str = "H264";
+const VIDEO_PAYLOAD_TYPE: u32 = 96;
+
+//Sessioni di rtpbin: stesso rtpbin, quindi stesso CNAME, e i client possono sincronizzare audio e video
+pub const VIDEO_SESSION: u32 = 0;
+pub const AUDIO_SESSION: u32 = 1;
+//L'RTCP di ogni flusso viaggia due porte dopo il suo RTP: video P, audio P+1, RTCP del video P+2, dell'audio P+3
+pub const RTCP_PORT_OFFSET: u16 = 2;
+
+
+/// Address of the RTCP of the RTP stream sent to `rtp_address`
+pub fn rtcp_address(rtp_address: SocketAddr) -> Option<SocketAddr> {
+    Some(SocketAddr::new(rtp_address.ip(), rtp_address.port().checked_add(RTCP_PORT_OFFSET)?))
+}
+
+/// Port `offset` after `rtp_port` that the server sends from, 0 (any port) stays 0
+pub fn source_port(rtp_port: u16, offset: u16) -> Result<u16, ServerError> {
+    match rtp_port {
+        0 => Ok(0),
+        port => port.checked_add(offset).ok_or_else(|| ServerError {
+            message: format!("RTP port {} leaves no room for the audio and RTCP ports", port),
+        }),
+    }
+}
+
+/// multiudpsink of the RTCP sender reports. They are sent as soon as rtpbin makes them, without waiting for the clock
+pub fn rtcp_sink(name: &str, bind_port: u16) -> Result<gst::Element, ServerError> {
+    gst::ElementFactory::make("multiudpsink")
+        .property("clients", "")
+        .property("bind-port", bind_port as i32)
+        .property("sync", false)
+        .property("async", false)
+        .name(name)
+        .build()
+        .map_err(|_| ServerError {
+            message: format!("Failed to create multiudpsink '{}'", name),
+        })
+}
+
+/// Sends what `payloader` produces through `session` of rtpbin: the RTP packets to `rtp_sink` and the sender reports,
+/// which the clients need to align audio and video, to `rtcp_sink`. With `srtp_key` both leave encrypted by the same srtpenc
+pub fn link_rtp_session(pipeline: &Pipeline, rtpbin: &gst::Element, session: u32, payloader: &gst::Element,
+    rtp_sink: &gst::Element, rtcp_sink: &gst::Element, srtp_key: Option<&SrtpKey>) -> Result<(), ServerError> {
+    let link_error = |what: &str| ServerError {
+        message: format!("Failed to link the {} of RTP session {}", what, session),
+    };
+    let rtp_src = format!("send_rtp_src_{}", session);
+    let rtcp_src = format!("send_rtcp_src_{}", session);
+
+    //le richieste dei pad per nome: send_rtp_src_N esiste solo dopo send_rtp_sink_N
+    payloader.link_pads(None, rtpbin, Some(&format!("send_rtp_sink_{}", session)))
+        .map_err(|_| link_error("payloader"))?;
+    match srtp_key {
+        Some(key) => {
+            let srtpenc = key.encoder(&format!("srtpenc_{}", session))?;
+            pipeline.add(&srtpenc).map_err(|_| ServerError {
+                message: format!("Failed to add the srtpenc of RTP session {} to pipeline", session),
+            })?;
+            rtpbin.link_pads(Some(&rtp_src), &srtpenc, Some("rtp_sink_0"))
+                .and_then(|_| srtpenc.link_pads(Some("rtp_src_0"), rtp_sink, None))
+                .map_err(|_| link_error("RTP packets"))?;
+            rtpbin.link_pads(Some(&rtcp_src), &srtpenc, Some("rtcp_sink_0"))
+                .and_then(|_| srtpenc.link_pads(Some("rtcp_src_0"), rtcp_sink, None))
+                .map_err(|_| link_error("sender reports"))?;
+        },
+        None => {
+            rtpbin.link_pads(Some(&rtp_src), rtp_sink, None).map_err(|_| link_error("RTP packets"))?;
+            rtpbin.link_pads(Some(&rtcp_src), rtcp_sink, None).map_err(|_| link_error("sender reports"))?;
+        },
+    }
+    Ok(())
+}
 
 
 pub struct DimensionToCrop {
@@ -13,31 +98,80 @@ pub struct DimensionToCrop {
     pub left: i32,
 }
 
+/// Pixel of the desktop under a point of the video, `stream` from 0 to 1 across the frame.
+/// The video is the capture `region` of a `source_size` screen placed at `origin`, scaled into `output_size`
+/// with the borders videoscale adds when the proportions differ: points on the borders go to the edge of the region
+pub fn stream_to_desktop(stream: (f64, f64), source_size: (i32, i32), region: &DimensionToCrop,
+    output_size: (i32, i32), origin: (i32, i32)) -> Option<(i32, i32)> {
+    let (stream_x, stream_y) = stream;
+    let (output_width, output_height) = (output_size.0 as f64, output_size.1 as f64);
+    let region_width = (source_size.0 - region.left - region.right) as f64;
+    let region_height = (source_size.1 - region.top - region.bottom) as f64;
+    if region_width <= 0.0 || region_height <= 0.0 || output_width <= 0.0 || output_height <= 0.0 {
+        return None;
+    }
+    let scale = (output_width / region_width).min(output_height / region_height);
+    let border_x = (output_width - region_width * scale) / 2.0;
+    let border_y = (output_height - region_height * scale) / 2.0;
+
+    let x = ((stream_x * output_width - border_x) / scale).clamp(0.0, region_width - 1.0);
+    let y = ((stream_y * output_height - border_y) / scale).clamp(0.0, region_height - 1.0);
+    Some((origin.0 + region.left + x as i32, origin.1 + region.top + y as i32))
+}
+
+
 pub struct ScreenStreamer {
     pipeline: Option<Pipeline>,
-    clients: Arc<Mutex<Vec<String>>>,
+    clients: ClientRegistry,
     is_streaming: bool,
     is_paused: bool,
     capture_region:DimensionToCrop,
-    monitor_index: usize,
+    config: StreamConfig,
+    placeholder: Option<PlaceholderBranch>,
+    showing_placeholder: bool,
+    paused: Option<PlaceholderBranch>,
+    srtp_key: Option<SrtpKey>, // None = stream in chiaro
+    pointers: Vec<PointerMark>, // l'ultimo punto indicato da ogni spettatore
+    screen_origin: Option<(i32, i32)>, // posizione della cattura sul desktop, None se non è lo schermo
+    remote: Option<RemoteControl>, // None = controllo remoto non disponibile
+    controller: Option<SocketAddr>,
+    source_ended: Arc<AtomicBool>, // la sorgente non manda più frame, es. è finito il file
 }
 
 impl ScreenStreamer {
     
-    pub fn new(dimension: DimensionToCrop, monitor_index: usize) -> Result<Self, ServerError> {  //for linux monitor id =valnode =extrainfo
+    pub fn new(dimension: DimensionToCrop, source: Box<dyn CaptureSource>, config: StreamConfig) -> Result<Self, ServerError> {
         gst::init().map_err(|e| ServerError {
             message: format!("Failed to initialize GStreamer: {}", e),
         })?;
 
-        let pipeline = Self::create_pipeline2(&dimension, monitor_index).expect("errore creazioen pipeline screenstremer");
+        config.check_confidential().map_err(|message| ServerError { message })?;
+        let srtp_key = match srtp::is_available() {
+            true => Some(SrtpKey::generate()),
+            false if config.confidential => return Err(ServerError {
+                message: "The session is confidential but SRTP is not available, install the srtp plugin of gst-plugins-bad".to_string(),
+            }),
+            false => {
+                println!("SRTP is not available, the stream will be sent unencrypted");
+                None
+            },
+        };
+
+        let source_ended = Arc::new(AtomicBool::new(false));
+        let pipeline = Self::create_pipeline2(source.as_ref(), &dimension, &config, srtp_key.as_ref(), &source_ended)?;
+        let screen_origin = source.screen_origin();
+        let remote = Self::remote_control(&config, screen_origin);
+        let placeholder = Self::build_placeholder(&pipeline, &Placeholder::blank())?;
 
         let bus = pipeline.bus().unwrap();
         let pipeline_clone = pipeline.clone();
+        let bus_ended = Arc::clone(&source_ended);
         std::thread::spawn(move || {
             for msg in bus.iter_timed(gst::ClockTime::NONE) {
                 match msg.view() {
                     gst::MessageView::Eos(..) => {
                         println!("End of stream");
+                        bus_ended.store(true, Ordering::Relaxed);
                         pipeline_clone.set_state(State::Null).unwrap();
                         break;
                     }
@@ -48,6 +182,7 @@ impl ScreenStreamer {
                             err.error()
                         );
                         println!("Debugging information: {:?}", err.debug());
+                        bus_ended.store(true, Ordering::Relaxed);
                         pipeline_clone.set_state(State::Null).unwrap();
                         break;
                     }
@@ -58,52 +193,55 @@ impl ScreenStreamer {
 
         Ok(Self {
             pipeline: Some(pipeline),
-            clients: Arc::new(Mutex::new(vec![])),
+            clients: ClientRegistry::new(),
             is_streaming: false,
             is_paused: false,
             capture_region : dimension,
-            monitor_index: monitor_index,
+            config,
+            placeholder: Some(placeholder),
+            showing_placeholder: false,
+            paused: None,
+            srtp_key,
+            pointers: Vec::new(),
+            screen_origin,
+            remote,
+            controller: None,
+            source_ended,
         })
     }
 
-
-    fn create_pipeline2(crop: &DimensionToCrop, monitor_index: usize) -> Result<Pipeline, ServerError> {
-
-        //Creazione dei videosource specializzate per ogni OS
-        #[cfg(target_os = "windows")]
-        let videosrc = gst::ElementFactory::make("d3d11screencapturesrc")
-            .property("show-cursor", true)
-            .property("monitor-index", monitor_index as i32)
-            //.property("show-border", true)
-            .build()
-            .map_err(|_| ServerError {
-                message: "Failed to create d3d11screencapturesrc".to_string(),
-            })?;
-
-        #[cfg(target_os = "macos")]
-        let videosrc = gst::ElementFactory::make("avfvideosrc")
-            .property("capture-screen", true)
-            .property("device-index", monitor_index as i32)
-            .build()
-            .map_err(|_| ServerError { message: "Failed to create avfvideosrc".to_string()})?;
-
-        cfg_if! {
-            if #[cfg(target_os = "linux")] {
-             
-                let videosrc = gst::ElementFactory::make("pipewiresrc")
-                    .property("path", monitor_index.to_string())
-                    .property("do-timestamp", true)
-                    .build()
-                    .map_err(|_| ServerError {
-                        message: "Failed to create pipewiresrc".to_string(),
-                    })?;
+    fn remote_control(config: &StreamConfig, screen_origin: Option<(i32, i32)>) -> Option<RemoteControl> {
+        if config.remote_control == RemoteControlMode::Disabled {
+            return None;
+        }
+        if screen_origin.is_none() {
+            //senza la posizione sul desktop i click finirebbero sul monitor sbagliato
+            println!("Remote control is only possible when a screen is shared and its position on the desktop is known");
+            return None;
+        }
+        match config.remote_control {
+            RemoteControlMode::DryRun => Some(RemoteControl::new(Box::new(RecordingInjector::default()))),
+            _ => match input::system_injector() {
+                Ok(injector) => Some(RemoteControl::new(injector)),
+                Err(e) => {
+                    println!("Remote control is not available: {}", e);
+                    None
+                },
+            },
         }
     }
 
 
+    fn create_pipeline2(source: &dyn CaptureSource, crop: &DimensionToCrop, config: &StreamConfig, srtp_key: Option<&SrtpKey>,
+        source_ended: &Arc<AtomicBool>) -> Result<Pipeline, ServerError> {
 
+        let pipeline = Pipeline::new();
+
+        //La sorgente aggiunge da sola i suoi elementi alla pipeline
+        let videosrc = source.build(&pipeline)?;
 
         let videocrop = gst::ElementFactory::make("videocrop")
+            .name("videocrop")
             .property("bottom", &crop.bottom)
             .property("top", &crop.top)
             .property("left", &crop.left)
@@ -113,23 +251,18 @@ impl ScreenStreamer {
                 message: "Failed to create videocrop".to_string(),
             })?;
 
-        cfg_if! {
-            if #[cfg(target_os = "linux")] {
-
-
-                let video_rate = gst::ElementFactory::make("videorate").property("max-rate", 30).property("drop-only", true)
-                .build()
-                    .map_err(|_| ServerError {
-                        message: "Failed to create videoRate".to_string(),
-                    })?;
-            }
-        }
+        //Not every source can negotiate the framerate by itself (pipewire, files), so it is limited here
+        let video_rate = gst::ElementFactory::make("videorate").property("max-rate", config.max_framerate).property("drop-only", true)
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create videoRate".to_string(),
+            })?;
 
         let capsfilter = gst::ElementFactory::make("capsfilter")
             .property(
                 "caps",
                 gst::Caps::builder("video/x-raw")
-                    .field("framerate", &gst::Fraction::new(30, 1))
+                    .field("framerate", &gst::Fraction::new(config.max_framerate, 1))
                     .build(),
             ).build()
             .map_err(|_| ServerError {
@@ -146,15 +279,70 @@ impl ScreenStreamer {
                 message: "Failed to create videoconvert".to_string(),
             })?;
 
+        //add-borders keeps the aspect ratio when the crop region changes during the stream
+        let videoscale = gst::ElementFactory::make("videoscale")
+            .property("add-borders", true)
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create videoscale".to_string(),
+            })?;
+
+        //Se non è stata scelta una risoluzione si lascia passare la dimensione dell'area catturata
+        let mut output_caps = gst::Caps::builder("video/x-raw");
+        if let Some((width, height)) = config.output_resolution.size() {
+            output_caps = output_caps.field("width", width).field("height", height);
+        }
+        let scalecaps = gst::ElementFactory::make("capsfilter")
+            .name("scalecaps")
+            .property("caps", output_caps.build())
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create capsfilter for the output resolution".to_string(),
+            })?;
+
+        //Sceglie tra la cattura e il placeholder, l'encoder dopo di lui è sempre lo stesso
+        let selector = gst::ElementFactory::make("input-selector")
+            .name("selector")
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create input-selector".to_string(),
+            })?;
+
+        let tee = gst::ElementFactory::make("tee").build()
+            .map_err(|_| ServerError {
+                message: "Failed to create tee".to_string(),
+            })?;
+
         let queue2 = gst::ElementFactory::make("queue").build()
             .map_err(|_| ServerError {
                 message: "Failed to create queue2".to_string(),
             })?;
 
+        //Tiene l'ultimo frame inviato, che viene mostrato oscurato durante la pausa
+        let queue_last_frame = gst::ElementFactory::make("queue")
+            .property_from_str("leaky", "downstream")
+            .property("max-size-buffers", 1u32)
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create queue for the last frame".to_string(),
+            })?;
+
+        let last_frame = gst::ElementFactory::make("fakesink")
+            .name("lastframe")
+            .property("sync", false)
+            .property("async", false)
+            .property("enable-last-sample", true)
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create fakesink for the last frame".to_string(),
+            })?;
+
         let x264enc = gst::ElementFactory::make("x264enc")
-            .property("bitrate", 5000  as u32) // Bitrate in kbps
-            .property_from_str("speed-preset", "ultrafast") // Faster encoding
+            .name("encoder")
+            .property("bitrate", config.bitrate) // Bitrate in kbps
+            .property_from_str("speed-preset", config.preset.as_str())
             .property_from_str("tune", "zerolatency") //For live streaming with low latency
+            .property("key-int-max", config.keyframe_interval)
             .build()
             .map_err(|_| ServerError {
                 message: "Failed to create x264enc".to_string(),
@@ -165,7 +353,11 @@ impl ScreenStreamer {
                 message: "Failed to create queue3".to_string(),
             })?;
 
-        let rtph264pay = gst::ElementFactory::make("rtph264pay").build()
+        //SPS/PPS prima di ogni IDR: chi si collega a metà può decodificare dal primo keyframe
+        let rtph264pay = gst::ElementFactory::make("rtph264pay")
+            .property("config-interval", -1i32)
+            .property("pt", VIDEO_PAYLOAD_TYPE)
+            .build()
             .map_err(|_| ServerError {
                 message: "Failed to create rtph264pay".to_string(),
             })?;
@@ -177,109 +369,399 @@ impl ScreenStreamer {
 
         let udpmulticastsink = gst::ElementFactory::make("multiudpsink")
             .property("clients", "")
+            .property("bind-port", config.rtp_port as i32) // 0 lascia scegliere al sistema
             .name("multiudpsink")
             .build()
             .map_err(|_| ServerError {
                 message: "Failed to create multiudpsink".to_string(),
             })?;
 
-        let pipeline = Pipeline::new();
-        pipeline.add(&videosrc).map_err(|_| ServerError {
-            message: "Failed to add videosrc to pipeline".to_string(),
-        })?;
+        let rtcp_sink = rtcp_sink("multiudpsink_rtcp", source_port(config.rtp_port, RTCP_PORT_OFFSET)?)?;
 
-        cfg_if! {
-            if #[cfg(target_os = "linux")] {
-                pipeline.add_many(&[
-                    &video_rate,
-                ]).map_err(|_| ServerError {
-                    message: "Failed to add elements to pipeline for linux".to_string(),
-                })?;
-            }
-        }
+        let rtpbin = gst::ElementFactory::make("rtpbin")
+            .name("rtpbin")
+            .build()
+            .map_err(|_| ServerError {
+                message: "Failed to create rtpbin".to_string(),
+            })?;
 
         pipeline.add_many(&[
+            &video_rate,
             &capsfilter,
             &videocrop,
             &queue1,
             &videoconvert,
+            &videoscale,
+            &scalecaps,
+            &selector,
+            &tee,
             &queue2,
+            &queue_last_frame,
+            &last_frame,
             &x264enc,
             &queue3,
             &rtph264pay,
             &queue4,
+            &rtpbin,
             &udpmulticastsink,
+            &rtcp_sink,
         ]).map_err(|_| ServerError {
             message: "Failed to add elements to pipeline".to_string(),
         })?;
 
-        cfg_if! {
-            if #[cfg(target_os = "linux")] {
-                gst::Element::link_many(&[
-                    &videosrc,
-                    &video_rate,
-                    &capsfilter,
-                ]).map_err(|_| ServerError {
-                    message: "Failed to link elements".to_string(),
-                })?;
-            } else {
-                gst::Element::link(&videosrc, &capsfilter).map_err(|_| ServerError {
-                    message: "Failed to link elements".to_string(),
-                })?;
-            }
-        }
+        gst::Element::link_many(&[
+            &videosrc,
+            &video_rate,
+            &capsfilter,
+        ]).map_err(|_| ServerError {
+            message: format!("Failed to link the source '{}'", source.name()),
+        })?;
 
         gst::Element::link_many(&[
             &capsfilter,
             &videocrop,
             &queue1,
             &videoconvert,
+            &videoscale,
+            &scalecaps,
+        ]).map_err(|_| ServerError {
+            message: "Failed to link elements".to_string(),
+        })?;
+
+        let live_pad = selector.request_pad_simple("sink_%u").ok_or(ServerError {
+            message: "Failed to request a pad from input-selector".to_string(),
+        })?;
+        //I segni degli spettatori vanno solo sulla cattura, non sui placeholder
+        let live_src = match pointer::is_available() {
+            true => {
+                let (dot, label) = pointer::overlays()?;
+                pipeline.add_many([&dot, &label]).map_err(|_| ServerError {
+                    message: "Failed to add the pointer overlay to pipeline".to_string(),
+                })?;
+                gst::Element::link_many([&scalecaps, &dot, &label]).map_err(|_| ServerError {
+                    message: "Failed to link the pointer overlay".to_string(),
+                })?;
+                label
+            },
+            false => {
+                println!("textoverlay is not available, the pointers of the viewers won't be shown in the stream");
+                scalecaps.clone()
+            },
+        };
+        let live_src_pad = live_src.static_pad("src").unwrap();
+        live_src_pad.link(&live_pad).map_err(|_| ServerError {
+            message: "Failed to link scalecaps to input-selector".to_string(),
+        })?;
+        selector.set_property("active-pad", &live_pad);
+
+        //L'EOS della cattura (fine del file, condivisione interrotta) non deve arrivare all'encoder mentre
+        //la sessione è ancora aperta: si segna la fine e chi usa lo streamer chiude la sessione con i client
+        let live_ended = Arc::clone(source_ended);
+        live_src_pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            match info.data {
+                Some(gst::PadProbeData::Event(ref event)) if event.type_() == gst::EventType::Eos => {
+                    println!("The capture source ended");
+                    live_ended.store(true, Ordering::Relaxed);
+                    gst::PadProbeReturn::Drop
+                },
+                _ => gst::PadProbeReturn::Ok,
+            }
+        });
+
+        //Il placeholder deve avere esattamente le caps della cattura, altrimenti al cambio l'encoder rinegozia
+        let pipeline_weak = pipeline.downgrade();
+        live_src_pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                if let gst::EventView::Caps(caps) = event.view() {
+                    if let Some(placeholder_caps) = pipeline_weak.upgrade().and_then(|p| p.by_name("placeholder_caps")) {
+                        placeholder_caps.set_property("caps", caps.caps().to_owned());
+                    }
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+        gst::Element::link_many(&[
+            &tee,
+            &queue_last_frame,
+            &last_frame,
+        ]).map_err(|_| ServerError {
+            message: "Failed to link the last frame branch".to_string(),
+        })?;
+
+        gst::Element::link_many(&[
+            &selector,
+            &tee,
             &queue2,
             &x264enc,
             &queue3,
             &rtph264pay,
             &queue4,
-            &udpmulticastsink,
         ]).map_err(|_| ServerError {
             message: "Failed to link elements".to_string(),
         })?;
 
+        //con SRTP i pacchetti vengono cifrati subito prima di uscire
+        link_rtp_session(&pipeline, &rtpbin, VIDEO_SESSION, &queue4, &udpmulticastsink, &rtcp_sink, srtp_key)?;
+
+        if config.audio.is_enabled() {
+            audio::add_audio_branch(&pipeline, &rtpbin, &config.audio, config.rtp_port, srtp_key)?;
+        }
+
         Ok(pipeline)
     }
 
-    pub fn update_clients(&self, client_list_str: String) {
+    /// Starts sending the stream to `addr`, the other clients are not touched
+    pub fn add_client(&mut self, addr: SocketAddr) {
+        if !self.clients.add(addr) {
+            return;
+        }
+        self.emit_on_sinks("add", addr);
 
-        let client_list = client_list_str.split(',').map(|s| s.to_string()).collect();
-        {
-            let mut clients = self.clients.lock().unwrap();
-            if client_list_str.is_empty(){
-                *clients = Vec::new();
-            }
-            else{
-                *clients = client_list;
+        //Senza un nuovo keyframe il nuovo client dovrebbe aspettare il prossimo IDR per vedere qualcosa
+        if let Err(e) = self.force_keyframe() {
+            println!("{}", e);
+        }
+    }
+
+    pub fn remove_client(&mut self, addr: SocketAddr) {
+        if self.clients.remove(&addr) {
+            self.emit_on_sinks("remove", addr);
+        }
+        if self.pointers.iter().any(|mark| mark.viewer == addr) {
+            self.pointers.retain(|mark| mark.viewer != addr);
+            self.update_pointer_overlay();
+        }
+        if self.controller == Some(addr) {
+            self.set_controller(None);
+        }
+    }
+
+    /// True once the capture source stopped for good (end of the file, error of the pipeline):
+    /// the session has to be stopped so that the clients receive `SessionEnded`
+    pub fn source_ended(&self) -> bool {
+        self.source_ended.load(Ordering::Relaxed)
+    }
+
+    /// True if the viewers can ask for the control of the desktop
+    pub fn remote_control_available(&self) -> bool {
+        self.remote.is_some()
+    }
+
+    /// Turns the remote control off for the rest of the session, e.g. when it could not be revoked from the keyboard
+    pub fn disable_remote_control(&mut self) {
+        self.set_controller(None);
+        self.remote = None;
+    }
+
+    /// The viewer whose input is injected, `None` when nobody is in control.
+    /// What the previous one was holding down is released
+    pub fn set_controller(&mut self, controller: Option<SocketAddr>) {
+        if self.controller == controller {
+            return;
+        }
+        if let Some(ref mut remote) = self.remote {
+            remote.release_all();
+        }
+        match controller {
+            Some(addr) => println!("Remote control granted to {}", addr),
+            None => println!("Remote control revoked"),
+        }
+        self.controller = controller;
+    }
+
+    /// Input of the viewer in control, dropped while the viewers are not seeing the screen
+    pub fn inject(&mut self, event: &InputEvent) {
+        if self.controller.is_none() || self.showing_placeholder || self.is_paused {
+            return;
+        }
+        let Some(mut remote) = self.remote.take() else {
+            return;
+        };
+        let Some(origin) = self.screen_origin else {
+            self.remote = Some(remote);
+            return;
+        };
+        let result = remote.apply(event, |x, y| self.stream_to_screen(x, y, origin));
+        if let Err(e) = result {
+            println!("Failed to inject the input of the viewer: {}", e);
+        }
+        self.remote = Some(remote);
+    }
+
+    /// A viewer clicked on the video, the mark replaces its previous one
+    pub fn point(&mut self, mark: PointerMark) {
+        self.pointers.retain(|known| known.viewer != mark.viewer);
+        self.pointers.push(mark);
+        self.update_pointer_overlay();
+    }
+
+    /// Removes the marks older than `POINTER_DURATION`, called periodically
+    pub fn expire_pointers(&mut self) {
+        if self.pointers.iter().any(PointerMark::is_expired) {
+            self.pointers.retain(|mark| !mark.is_expired());
+            self.update_pointer_overlay();
+        }
+    }
+
+    /// Marks of the viewers still visible, the most recent last
+    pub fn pointers(&self) -> Vec<PointerMark> {
+        self.pointers.iter().filter(|mark| !mark.is_expired()).cloned().collect()
+    }
+
+    /// With `shared` the last mark is drawn on the stream, so every viewer sees it
+    pub fn set_pointers_shared(&mut self, shared: bool) {
+        self.config.share_pointers = shared;
+        self.update_pointer_overlay();
+    }
+
+    fn update_pointer_overlay(&self) {
+        let Some(pipeline) = self.pipeline.as_ref() else {
+            return;
+        };
+        let (Some(dot), Some(label)) = (pipeline.by_name("pointeroverlay"), pipeline.by_name("pointerlabel")) else {
+            return;
+        };
+        let mark = match self.config.share_pointers {
+            true => self.pointers.last(),
+            false => None,
+        };
+        pointer::show(&dot, &label, mark);
+    }
+
+    /// Size of the video sent to the clients, once negotiated
+    pub fn output_size(&self) -> Option<(i32, i32)> {
+        self.pipeline.as_ref()
+            .and_then(|pipeline| pipeline.by_name("scalecaps"))
+            .and_then(|scalecaps| Self::negotiated_size(&scalecaps))
+    }
+
+    /// Pixel of the captured screen under `mark`, taking into account the capture region
+    /// and the borders videoscale adds when the region changed during the stream
+    pub fn pointer_on_screen(&self, mark: &PointerMark) -> Option<(i32, i32)> {
+        self.stream_to_screen(mark.x, mark.y, (0, 0))
+    }
+
+    fn stream_to_screen(&self, stream_x: f64, stream_y: f64, origin: (i32, i32)) -> Option<(i32, i32)> {
+        let pipeline = self.pipeline.as_ref()?;
+        let source_size = pipeline.by_name("videocrop")
+            .and_then(|videocrop| videocrop.static_pad("sink"))
+            .and_then(|pad| pad.current_caps())
+            .and_then(|caps| {
+                let structure = caps.structure(0)?;
+                Some((structure.get::<i32>("width").ok()?, structure.get::<i32>("height").ok()?))
+            })?;
+        let output_size = self.output_size()?;
+        stream_to_desktop((stream_x, stream_y), source_size, &self.capture_region, output_size, origin)
+    }
+
+    /// Caps of the RTP video stream, the clients use them for their udpsrc
+    pub fn video_caps(&self) -> String {
+        gst::Caps::builder("application/x-rtp")
+            .field("media", "video")
+            .field("clock-rate", 90000)
+            .field("encoding-name", VIDEO_CODEC)
+            .field("payload", VIDEO_PAYLOAD_TYPE as i32)
+            .build()
+            .to_string()
+    }
+
+    /// Caps of the RTP audio stream, `None` if no audio is sent
+    pub fn audio_caps(&self) -> Option<String> {
+        self.config.audio.is_enabled().then(|| audio::rtp_caps().to_string())
+    }
+
+    /// Key the clients need to decrypt the stream, None if it is sent unencrypted.
+    /// The discovery server never sends it as it is, see `SrtpKey::wrap`
+    pub fn srtp_key(&self) -> Option<SrtpKey> {
+        self.srtp_key.clone()
+    }
+
+    pub fn has_clients(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
+    /// Calls the `add`/`remove` action of the multiudpsinks, for the video and (if present) the audio one, with their RTCP
+    fn emit_on_sinks(&self, action: &str, addr: SocketAddr) {
+        let Some(ref pipeline) = self.pipeline else {
+            return;
+        };
+        //l'audio, se presente, va alla porta successiva del client, l'RTCP di ognuno due porte dopo
+        let audio_addr = audio::audio_address(addr);
+        let sinks = [
+            ("multiudpsink", Some(addr)),
+            ("multiudpsink_rtcp", rtcp_address(addr)),
+            ("multiudpsink_audio", audio_addr),
+            ("multiudpsink_audio_rtcp", audio_addr.and_then(rtcp_address)),
+        ];
+        for (name, target) in sinks {
+            if let (Some(sink), Some(target)) = (pipeline.by_name(name), target) {
+                sink.emit_by_name::<()>(action, &[&target.ip().to_string(), &(target.port() as i32)]);
             }
-            
         }
-        self.update_multiudpsink();
     }
 
-    
+    /// Changes the streamed area without recreating the pipeline.
+    /// The output keeps the size the clients are already decoding, the new area is scaled into it
+    pub fn set_capture_region(&mut self, region: DimensionToCrop) -> Result<(), ServerError> {
+        let pipeline = self.pipeline.as_ref().ok_or(ServerError {
+            message: "Pipeline is not initialized".to_string(),
+        })?;
+        let videocrop = pipeline.by_name("videocrop").ok_or(ServerError {
+            message: "videocrop element not found".to_string(),
+        })?;
+        let scalecaps = pipeline.by_name("scalecaps").ok_or(ServerError {
+            message: "scalecaps element not found".to_string(),
+        })?;
+
+        //Con la risoluzione nativa il capsfilter non fissa nessuna dimensione: va bloccata
+        //su quella attuale prima di cambiare il crop, altrimenti l'encoder rinegozia
+        if self.config.output_resolution.size().is_none() {
+            if let Some((width, height)) = Self::negotiated_size(&scalecaps) {
+                scalecaps.set_property("caps", gst::Caps::builder("video/x-raw")
+                    .field("width", width)
+                    .field("height", height)
+                    .field("pixel-aspect-ratio", gst::Fraction::new(1, 1))
+                    .build());
+            }
+        }
 
-    fn update_multiudpsink(&self) {
-        if let Some(pipeline) = &self.pipeline {
-            let multiudpsink = pipeline
-                .by_name("multiudpsink")
-                .expect("Multiudpsink element not found");
+        videocrop.set_property("top", region.top);
+        videocrop.set_property("bottom", region.bottom);
+        videocrop.set_property("left", region.left);
+        videocrop.set_property("right", region.right);
 
+        println!("Capture region changed to top {} bottom {} left {} right {}",
+            region.top, region.bottom, region.left, region.right);
+        self.capture_region = region;
 
-            let clients = self.clients.lock().unwrap();
-            let addresses: Vec<String> = clients.iter().map(|addr| addr.to_string()).collect();
-            let addresses_str = addresses.join(",");
+        Ok(())
+    }
 
+    fn negotiated_size(element: &gst::Element) -> Option<(i32, i32)> {
+        let caps = element.static_pad("src")?.current_caps()?;
+        let structure = caps.structure(0)?;
+        Some((structure.get::<i32>("width").ok()?, structure.get::<i32>("height").ok()?))
+    }
 
-            multiudpsink
-                .set_property("clients", &addresses_str);
-            
+    pub fn set_system_audio_muted(&self, muted: bool) -> bool {
+        self.set_volume_muted(SYSTEM_VOLUME, muted)
+    }
+
+    pub fn set_microphone_muted(&self, muted: bool) -> bool {
+        self.set_volume_muted(MICROPHONE_VOLUME, muted)
+    }
+
+    fn set_volume_muted(&self, volume_name: &str, muted: bool) -> bool {
+        match self.pipeline.as_ref().and_then(|pipeline| pipeline.by_name(volume_name)) {
+            Some(volume) => {
+                volume.set_property("mute", muted);
+                true
+            },
+            None => {
+                println!("{} is not part of the pipeline, cannot change mute", volume_name);
+                false
+            },
         }
     }
 
@@ -291,133 +773,237 @@ impl ScreenStreamer {
 
         Ok(())
     }
-    pub fn restart(&mut self) -> bool {
-        if let Some(ref oldpipeline) = self.pipeline {
-            oldpipeline.set_state(gst::State::Null).map_err(|_e| ServerError {
-                message: format!("Failed to set old pipeline state to null"),
-            }).expect("errore stopping old pipeline");
-        
+    /// Keeps sending the last frame, dimmed and with "Paused" over it, instead of stopping the pipeline
+    pub fn pause(&mut self) -> bool {
+        if !self.is_streaming || self.is_paused {
+            println!("pipeline is not streaming, cannot pause");
+            return false;
         }
-        let pipe=ScreenStreamer::create_pipeline2(&self.capture_region, self.monitor_index).expect("Error reCreating the pipeline");
-
-        //self.is_streaming = true;
-        self.pipeline = Some(pipe);
-        ScreenStreamer::update_multiudpsink(self);
-        let restart_result = ScreenStreamer::start(self);
-
-        match restart_result {
-            Ok(_) => {
-                println!("Streaming is restarting");
-                return true;
+        match self.show_paused_frame() {
+            Ok(()) => {
+                println!("Pause the streaming correctly");
+                self.is_paused = true;
+                true
             },
             Err(e) => {
-                println!("Error in restarting the streaming: {}", e);
-                return false;
+                println!("Error in pausing the screen: {}", e);
+                false
             },
         }
     }
 
-    pub fn pause(&mut self) -> bool {
-        if let Some(ref pipeline) = self.pipeline {
-            if self.is_streaming {
-                let pause_result = pipeline.set_state(State::Paused);
-                match pause_result {
-                    Ok(_) => {
-                        println!("Pause the streaming correctly");
-                        self.is_paused = true;
-                        return true;
-                    },
-                    Err(e) => {
-                        println!("Error in pausing the screen: {}", e);
-                        return false;
-                    },
-                }
+
+    pub fn un_pause(&mut self) -> bool {
+        if !self.is_paused {
+            println!("streaming is not paused, cannot resume");
+            return false;
+        }
+        self.is_paused = false;
+        let result = self.switch_to_current().and_then(|()| {
+            let pipeline = self.pipeline.as_ref().ok_or(ServerError {
+                message: "Pipeline is not initialized".to_string(),
+            })?;
+            if let Some(paused) = self.paused.take() {
+                paused.remove(pipeline, &self.selector()?);
             }
+            self.force_keyframe()
+        });
+        match result {
+            Ok(()) => {
+                println!("UNPause the streaming correctly");
+                true
+            },
+            Err(e) => {
+                println!("Error in UNpausing the screen: {}", e);
+                self.is_paused = true;
+                false
+            },
         }
-        println!("pipeline does not exist, cannot pause");
-        return false;
     }
 
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
 
-    pub fn un_pause(&mut self) -> bool {
-        if let Some(ref pipeline) = self.pipeline {
-            if self.is_streaming {
-                let pause_result = pipeline.set_state(State::Playing);
-                match pause_result {
-                    Ok(_) => {
-                        println!("UNPause the streaming correctly");
-                        self.is_paused = true;
-                        return true;
-                    },
-                    Err(e) => {
-                        println!("Error in UNpausing the screen: {}", e);
-                        return false;
-                    },
-                }
-            }
+    fn show_paused_frame(&mut self) -> Result<(), ServerError> {
+        let pipeline = self.pipeline.as_ref().ok_or(ServerError {
+            message: "Pipeline is not initialized".to_string(),
+        })?;
+        let selector = self.selector()?;
+        let last_frame = pipeline.by_name("lastframe")
+            .and_then(|fakesink| fakesink.property::<Option<gst::Sample>>("last-sample"));
+        let output_caps = pipeline.by_name("scalecaps")
+            .and_then(|scalecaps| scalecaps.static_pad("src"))
+            .and_then(|pad| pad.current_caps());
+
+        let branch = PlaceholderBranch::paused(pipeline, &selector, last_frame, output_caps)?;
+        for element in &branch.elements {
+            element.sync_state_with_parent().map_err(|_| ServerError {
+                message: "Failed to start the paused frame".to_string(),
+            })?;
         }
-        println!("pipeline does not exist, cannot pause");
-        return false;
+        selector.set_property("active-pad", &branch.pad);
+        self.paused = Some(branch);
+        Ok(())
     }
 
+    /// Asks the encoder for a new IDR, so the clients can decode again right away
+    fn force_keyframe(&self) -> Result<(), ServerError> {
+        let encoder = self.pipeline.as_ref()
+            .and_then(|pipeline| pipeline.by_name("encoder"))
+            .ok_or(ServerError {
+                message: "encoder element not found".to_string(),
+            })?;
+        //con all_headers l'encoder rimanda anche SPS/PPS, servono a chi si è appena collegato
+        let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+            .all_headers(true)
+            .build();
+        //l'evento upstream va mandato al pad src dell'encoder
+        let sent = encoder.static_pad("src").map(|pad| pad.send_event(event)).unwrap_or(false);
+        if !sent {
+            return Err(ServerError {
+                message: "Failed to request a keyframe".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Sends `placeholder` to the viewers instead of the captured screen, through the same encoder.
+    /// With `None` the last placeholder is shown again
+    pub fn show_placeholder(&mut self, placeholder: Option<&Placeholder>) -> Result<(), ServerError> {
+        if let Some(placeholder) = placeholder {
+            self.set_placeholder(placeholder)?;
+        }
+        if self.placeholder.is_none() {
+            return Err(ServerError {
+                message: "There is no placeholder to show".to_string(),
+            });
+        }
+        self.showing_placeholder = true;
+        self.switch_to_current()
+    }
 
+    /// Goes back to the captured screen after `show_placeholder`
+    pub fn show_live(&mut self) -> Result<(), ServerError> {
+        self.showing_placeholder = false;
+        self.switch_to_current()
+    }
 
+    /// Selects the input the clients should see now. While paused the paused frame stays on
+    /// and the choice is applied by `un_pause`
+    fn switch_to_current(&self) -> Result<(), ServerError> {
+        if self.is_paused {
+            return Ok(());
+        }
+        let selector = self.selector()?;
+        let pad = match self.placeholder {
+            Some(ref branch) if self.showing_placeholder => branch.pad.clone(),
+            _ => selector.static_pad(LIVE_PAD).ok_or(ServerError {
+                message: "Live pad of input-selector not found".to_string(),
+            })?,
+        };
+        selector.set_property("active-pad", &pad);
+        Ok(())
+    }
 
+    /// Replaces the placeholder branch, also while the pipeline is playing
+    fn set_placeholder(&mut self, placeholder: &Placeholder) -> Result<(), ServerError> {
+        let pipeline = self.pipeline.as_ref().ok_or(ServerError {
+            message: "Pipeline is not initialized".to_string(),
+        })?;
+        let selector = self.selector()?;
 
-    pub fn share_static_image_end(&mut self,imagename: String) -> Result<(), ServerError> {
-        if let Some(ref oldpipeline) = self.pipeline {
-            oldpipeline.set_state(gst::State::Null).map_err(|_e| ServerError {
-                message: format!("Failed to set old pipeline state to null"),
+        if let Some(old) = self.placeholder.take() {
+            old.remove(pipeline, &selector);
+        }
+        let branch = Self::build_placeholder(pipeline, placeholder)?;
+        for element in &branch.elements {
+            element.sync_state_with_parent().map_err(|_| ServerError {
+                message: "Failed to start the placeholder".to_string(),
             })?;
-        
         }
+        self.placeholder = Some(branch);
+        self.switch_to_current()
+    }
 
-        let image_path="src/images/".to_string()+imagename.trim();
+    fn build_placeholder(pipeline: &Pipeline, placeholder: &Placeholder) -> Result<PlaceholderBranch, ServerError> {
+        let selector = pipeline.by_name("selector").ok_or(ServerError {
+            message: "input-selector element not found".to_string(),
+        })?;
+        //Se la cattura ha già negoziato si parte dalle sue caps, poi ci pensa la probe su scalecaps
+        let output_caps = pipeline.by_name("scalecaps")
+            .and_then(|scalecaps| scalecaps.static_pad("src"))
+            .and_then(|pad| pad.current_caps());
+        PlaceholderBranch::build(pipeline, &selector, placeholder, output_caps)
+    }
 
-       
-        let clients = self.clients.lock().unwrap();
+    fn selector(&self) -> Result<gst::Element, ServerError> {
+        self.pipeline.as_ref()
+            .and_then(|pipeline| pipeline.by_name("selector"))
+            .ok_or(ServerError {
+                message: "input-selector element not found".to_string(),
+            })
+    }
+}
 
-        if clients.len() == 0 { //We don't have any clients to stream to, so we don't need to start the pipeline
-            return Ok({});
-        }
-        
-        
-        let addresses: Vec<String> = clients.iter().map(|addr| addr.to_string()).collect();
-        let addresses_str = addresses.join(",");
-
-        let pipeline_description = format!(r#"
-        multifilesrc location={} loop=true !
-        pngdec !
-        videorate !
-        video/x-raw,framerate=60/1 !
-        videoconvert !
-        x264enc !
-        rtph264pay !
-        multiudpsink name=multiudpsink clients={}
-    "#, &image_path,&addresses_str
-);
-
-
-let pipeline = gst::parse::launch(pipeline_description.as_str()).expect("Failed to parse pipeline to gst::parse");
-// Start playing the pipeline
-let new_pipeline = pipeline.dynamic_cast::<gst::Pipeline>().expect("Failed to cast pipeline to gst::Pipeline");
-
-
-    let res = new_pipeline.set_state(State::Playing).map_err(|_| "Failed to set pipeline of static image to Playing ".to_string());
-
-    match res {
-        Ok(_) => {
-            self.pipeline = Some(new_pipeline);
-            self.is_streaming = true;
-            println!("State changed correctly");
-        },
-        Err(e) => {
-            println!("Problem setting the state: {}", e);
-        },
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(top: i32, bottom: i32, left: i32, right: i32) -> DimensionToCrop {
+        DimensionToCrop { top, bottom, right, left }
+    }
+
+    #[test]
+    fn full_screen_maps_to_the_same_pixels() {
+        let full = region(0, 0, 0, 0);
+        assert_eq!(stream_to_desktop((0.0, 0.0), (1920, 1080), &full, (1920, 1080), (0, 0)), Some((0, 0)));
+        assert_eq!(stream_to_desktop((0.5, 0.5), (1920, 1080), &full, (1920, 1080), (0, 0)), Some((960, 540)));
+        assert_eq!(stream_to_desktop((1.0, 1.0), (1920, 1080), &full, (1920, 1080), (0, 0)), Some((1919, 1079)));
+    }
+
+    #[test]
+    fn crop_and_scaling_are_undone() {
+        // area 800x600 a partire da (100, 50), mandata a 400x300
+        let crop = region(50, 430, 100, 1020);
+        assert_eq!(stream_to_desktop((0.0, 0.0), (1920, 1080), &crop, (400, 300), (0, 0)), Some((100, 50)));
+        assert_eq!(stream_to_desktop((0.5, 0.5), (1920, 1080), &crop, (400, 300), (0, 0)), Some((500, 350)));
     }
-            Ok({})
-        
+
+    #[test]
+    fn borders_are_skipped_and_clamped() {
+        // area quadrata 500x500 in un video 1000x500: 250 pixel di bordo a sinistra e a destra
+        let square = region(0, 580, 0, 1420);
+        assert_eq!(stream_to_desktop((0.25, 0.0), (1920, 1080), &square, (1000, 500), (0, 0)), Some((0, 0)));
+        assert_eq!(stream_to_desktop((0.5, 0.5), (1920, 1080), &square, (1000, 500), (0, 0)), Some((250, 250)));
+        // un click sul bordo finisce sul lato dell'area
+        assert_eq!(stream_to_desktop((0.1, 0.5), (1920, 1080), &square, (1000, 500), (0, 0)), Some((0, 250)));
+        assert_eq!(stream_to_desktop((0.9, 0.5), (1920, 1080), &square, (1000, 500), (0, 0)), Some((499, 250)));
+    }
+
+    #[test]
+    fn monitor_origin_is_added() {
+        let crop = region(10, 0, 20, 0);
+        assert_eq!(stream_to_desktop((0.0, 0.0), (1920, 1080), &crop, (1900, 1070), (1920, -200)), Some((1940, -190)));
+    }
+
+    #[test]
+    fn empty_region_maps_nowhere() {
+        let everything = region(540, 540, 0, 0);
+        assert_eq!(stream_to_desktop((0.5, 0.5), (1920, 1080), &everything, (1920, 1080), (0, 0)), None);
+    }
+
+    #[test]
+    fn rtcp_goes_two_ports_after_its_stream() {
+        let client: SocketAddr = "192.168.1.20:5000".parse().unwrap();
+        assert_eq!(rtcp_address(client), Some("192.168.1.20:5002".parse().unwrap()));
+        let audio = audio::audio_address(client).unwrap();
+        assert_eq!(rtcp_address(audio), Some("192.168.1.20:5003".parse().unwrap()));
+        assert_eq!(rtcp_address("192.168.1.20:65534".parse().unwrap()), None);
+
+        assert_eq!(source_port(0, RTCP_PORT_OFFSET).unwrap(), 0);
+        assert_eq!(source_port(6000, 1 + RTCP_PORT_OFFSET).unwrap(), 6003);
+        assert!(source_port(65534, RTCP_PORT_OFFSET).is_err());
     }
-        
- 
 }
@@ -0,0 +1,162 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::{mem, slice};
+use crate::streamer::error::ServerError;
+use crate::streamer::input::{InputInjector, KeyCode};
+
+
+//ioctl di linux/uinput.h, libc non li esporta
+const UI_SET_EVBIT: libc::c_ulong = 0x4004_5564;
+const UI_SET_KEYBIT: libc::c_ulong = 0x4004_5565;
+const UI_SET_RELBIT: libc::c_ulong = 0x4004_5566;
+const UI_SET_ABSBIT: libc::c_ulong = 0x4004_5567;
+const UI_DEV_CREATE: libc::c_ulong = 0x5501;
+const UI_DEV_DESTROY: libc::c_ulong = 0x5502;
+
+const EV_SYN: u16 = 0;
+const EV_KEY: u16 = 1;
+const EV_REL: u16 = 2;
+const EV_ABS: u16 = 3;
+const SYN_REPORT: u16 = 0;
+const REL_HWHEEL: u16 = 6;
+const REL_WHEEL: u16 = 8;
+const ABS_X: u16 = 0;
+const ABS_Y: u16 = 1;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+
+const DEVICE_NAME: &str = "condividi_schermo remote control";
+
+
+fn uinput_error(what: &str) -> ServerError {
+    ServerError {
+        message: format!("uinput: {} ({})", what, std::io::Error::last_os_error()),
+    }
+}
+
+
+/// Virtual mouse and keyboard created through /dev/uinput, works under Wayland too.
+/// The mouse is absolute and spans the whole desktop, so the position does not depend on the acceleration
+pub struct UinputInjector {
+    device: File,
+}
+
+impl UinputInjector {
+    pub fn create(width: i32, height: i32) -> Result<Self, ServerError> {
+        let device = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/uinput")
+            .map_err(|e| ServerError {
+                message: format!("uinput: cannot open /dev/uinput ({})", e),
+            })?;
+        let fd = device.as_raw_fd();
+
+        let ioctl = |request: libc::c_ulong, value: u16| {
+            // SAFETY: fd è aperto e le richieste UI_SET_* prendono un intero
+            if unsafe { libc::ioctl(fd, request, value as libc::c_int) } < 0 {
+                Err(uinput_error("device setup failed"))
+            } else {
+                Ok(())
+            }
+        };
+
+        ioctl(UI_SET_EVBIT, EV_KEY)?;
+        for key in KeyCode::all() {
+            ioctl(UI_SET_KEYBIT, key.evdev)?;
+        }
+        for button in [BTN_LEFT, BTN_RIGHT, BTN_MIDDLE] {
+            ioctl(UI_SET_KEYBIT, button)?;
+        }
+        ioctl(UI_SET_EVBIT, EV_REL)?;
+        ioctl(UI_SET_RELBIT, REL_WHEEL)?;
+        ioctl(UI_SET_RELBIT, REL_HWHEEL)?;
+        ioctl(UI_SET_EVBIT, EV_ABS)?;
+        ioctl(UI_SET_ABSBIT, ABS_X)?;
+        ioctl(UI_SET_ABSBIT, ABS_Y)?;
+
+        // SAFETY: uinput_user_dev è una struct C di soli interi, tutta a zero è valida
+        let mut setup: libc::uinput_user_dev = unsafe { mem::zeroed() };
+        for (dst, src) in setup.name.iter_mut().zip(DEVICE_NAME.bytes()) {
+            *dst = src as libc::c_char;
+        }
+        setup.id.bustype = 0x06; // BUS_VIRTUAL
+        setup.id.vendor = 0x1;
+        setup.id.product = 0x1;
+        setup.absmax[ABS_X as usize] = (width - 1).max(1);
+        setup.absmax[ABS_Y as usize] = (height - 1).max(1);
+
+        // SAFETY: si scrive la struct così com'è, come vuole il vecchio protocollo di uinput
+        let bytes = unsafe {
+            slice::from_raw_parts(&setup as *const _ as *const u8, mem::size_of::<libc::uinput_user_dev>())
+        };
+        (&device).write_all(bytes).map_err(|e| ServerError {
+            message: format!("uinput: device setup failed ({})", e),
+        })?;
+
+        // SAFETY: fd è aperto
+        if unsafe { libc::ioctl(fd, UI_DEV_CREATE) } < 0 {
+            return Err(uinput_error("cannot create the device"));
+        }
+
+        Ok(Self { device })
+    }
+
+    fn emit(&mut self, events: &[(u16, u16, i32)]) -> Result<(), ServerError> {
+        let report = [(EV_SYN, SYN_REPORT, 0)];
+        for (type_, code, value) in events.iter().chain(report.iter()) {
+            // SAFETY: input_event è una struct C, il timestamp a zero lo mette il kernel
+            let mut event: libc::input_event = unsafe { mem::zeroed() };
+            event.type_ = *type_;
+            event.code = *code;
+            event.value = *value;
+            // SAFETY: si scrive la struct così com'è, è il formato letto dal kernel
+            let bytes = unsafe {
+                slice::from_raw_parts(&event as *const _ as *const u8, mem::size_of::<libc::input_event>())
+            };
+            self.device.write_all(bytes).map_err(|e| ServerError {
+                message: format!("uinput: {}", e),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl InputInjector for UinputInjector {
+    fn name(&self) -> String {
+        "uinput".to_string()
+    }
+
+    fn move_to(&mut self, x: i32, y: i32) -> Result<(), ServerError> {
+        self.emit(&[(EV_ABS, ABS_X, x), (EV_ABS, ABS_Y, y)])
+    }
+
+    fn button(&mut self, button: u8, pressed: bool) -> Result<(), ServerError> {
+        let code = match button {
+            1 => BTN_LEFT,
+            2 => BTN_MIDDLE,
+            3 => BTN_RIGHT,
+            _ => return Ok(()),
+        };
+        self.emit(&[(EV_KEY, code, pressed as i32)])
+    }
+
+    fn scroll(&mut self, dx: i32, dy: i32) -> Result<(), ServerError> {
+        //per il kernel la rotella in su è positiva
+        self.emit(&[(EV_REL, REL_WHEEL, -dy), (EV_REL, REL_HWHEEL, dx)])
+    }
+
+    fn key(&mut self, key: &KeyCode, pressed: bool) -> Result<(), ServerError> {
+        self.emit(&[(EV_KEY, key.evdev, pressed as i32)])
+    }
+}
+
+impl Drop for UinputInjector {
+    fn drop(&mut self) {
+        // SAFETY: fd è ancora aperto, il file si chiude dopo
+        unsafe { libc::ioctl(self.device.as_raw_fd(), UI_DEV_DESTROY) };
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use x11rb::connection::Connection;
+use x11rb::protocol::Event;
+use x11rb::protocol::xproto::{self, ConnectionExt as _, GrabMode, Keycode, ModMask, Window};
+use x11rb::protocol::xtest::ConnectionExt as _;
+use x11rb::rust_connection::RustConnection;
+use crate::streamer::error::ServerError;
+use crate::streamer::hotkey::HOTKEY_POLL_INTERVAL;
+use crate::streamer::input::{InputInjector, KeyCode};
+
+
+const ESCAPE_KEYSYM: u32 = 0xff1b;
+
+
+fn x11_error(e: impl Display) -> ServerError {
+    ServerError {
+        message: format!("X11: {}", e),
+    }
+}
+
+//Per ogni keycode le keysym che produce, per trovare il tasto da premere
+fn keyboard_map(conn: &RustConnection) -> Result<Vec<(u32, Keycode)>, ServerError> {
+    let setup = conn.setup();
+    let (min_keycode, max_keycode) = (setup.min_keycode, setup.max_keycode);
+    let reply = conn.get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?;
+    let per_keycode = reply.keysyms_per_keycode.max(1) as usize;
+    Ok(reply.keysyms
+        .chunks(per_keycode)
+        .enumerate()
+        .flat_map(|(index, keysyms)| keysyms.iter().map(move |keysym| (*keysym, min_keycode + index as u8)))
+        .filter(|(keysym, _)| *keysym != 0)
+        .collect())
+}
+
+
+/// Injects the input through the XTest extension of the X server the presenter is using
+pub struct XTestInjector {
+    conn: RustConnection,
+    root: Window,
+    keycodes: Vec<(u32, Keycode)>,
+}
+
+impl XTestInjector {
+    pub fn connect() -> Result<Self, ServerError> {
+        let (conn, screen) = x11rb::connect(None).map_err(x11_error)?;
+        let root = conn.setup().roots[screen].root;
+        conn.xtest_get_version(2, 2).map_err(x11_error)?.reply().map_err(x11_error)?;
+        let keycodes = keyboard_map(&conn)?;
+        Ok(Self { conn, root, keycodes })
+    }
+
+    fn fake_input(&self, event_type: u8, detail: u8, x: i16, y: i16) -> Result<(), ServerError> {
+        self.conn.xtest_fake_input(event_type, detail, x11rb::CURRENT_TIME, self.root, x, y, 0)
+            .map_err(x11_error)?;
+        self.conn.flush().map_err(x11_error)
+    }
+
+    fn click(&self, button: u8, times: i32) -> Result<(), ServerError> {
+        for _ in 0..times {
+            self.fake_input(xproto::BUTTON_PRESS_EVENT, button, 0, 0)?;
+            self.fake_input(xproto::BUTTON_RELEASE_EVENT, button, 0, 0)?;
+        }
+        Ok(())
+    }
+}
+
+impl InputInjector for XTestInjector {
+    fn name(&self) -> String {
+        "XTest".to_string()
+    }
+
+    fn move_to(&mut self, x: i32, y: i32) -> Result<(), ServerError> {
+        self.fake_input(xproto::MOTION_NOTIFY_EVENT, 0, x as i16, y as i16)
+    }
+
+    fn button(&mut self, button: u8, pressed: bool) -> Result<(), ServerError> {
+        let event_type = if pressed { xproto::BUTTON_PRESS_EVENT } else { xproto::BUTTON_RELEASE_EVENT };
+        self.fake_input(event_type, button, 0, 0)
+    }
+
+    fn scroll(&mut self, dx: i32, dy: i32) -> Result<(), ServerError> {
+        //in X11 la rotella sono i pulsanti 4 e 5, lo scorrimento orizzontale 6 e 7
+        self.click(if dy < 0 { 4 } else { 5 }, dy.abs())?;
+        self.click(if dx < 0 { 6 } else { 7 }, dx.abs())
+    }
+
+    fn key(&mut self, key: &KeyCode, pressed: bool) -> Result<(), ServerError> {
+        let Some((_, keycode)) = self.keycodes.iter().find(|(keysym, _)| *keysym == key.keysym) else {
+            println!("No key of the presenter's keyboard produces keysym {:#x}", key.keysym);
+            return Ok(());
+        };
+        let event_type = if pressed { xproto::KEY_PRESS_EVENT } else { xproto::KEY_RELEASE_EVENT };
+        self.fake_input(event_type, *keycode, 0, 0)
+    }
+}
+
+
+/// Grabs Ctrl+Shift+Escape on the whole X display and calls `on_panic` at every press, until `stop` is set
+pub fn grab_panic_hotkey(stop: Arc<AtomicBool>, on_panic: impl Fn() + Send + 'static) -> Result<(), ServerError> {
+    let (conn, screen) = x11rb::connect(None).map_err(x11_error)?;
+    let root = conn.setup().roots[screen].root;
+    let keycode = keyboard_map(&conn)?
+        .into_iter()
+        .find(|(keysym, _)| *keysym == ESCAPE_KEYSYM)
+        .map(|(_, keycode)| keycode)
+        .ok_or_else(|| x11_error("no Escape key"))?;
+
+    // con Bloc Num o Bloc Maiusc attivi i modificatori sono diversi, si registrano tutte le combinazioni
+    for locks in [ModMask::from(0u16), ModMask::LOCK, ModMask::M2, ModMask::LOCK | ModMask::M2] {
+        conn.grab_key(true, root, ModMask::CONTROL | ModMask::SHIFT | locks, keycode, GrabMode::ASYNC, GrabMode::ASYNC)
+            .map_err(x11_error)?
+            .check()
+            .map_err(x11_error)?;
+    }
+
+    thread::spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+            match conn.poll_for_event() {
+                Ok(Some(Event::KeyPress(_))) => on_panic(),
+                Ok(Some(_)) => {},
+                Ok(None) => thread::sleep(HOTKEY_POLL_INTERVAL),
+                Err(e) => {
+                    println!("Panic hotkey stopped: {}", e);
+                    break;
+                },
+            }
+        }
+        //la connessione si chiude qui e con lei le grab
+    });
+    Ok(())
+}